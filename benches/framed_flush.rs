@@ -0,0 +1,118 @@
+//! Compares flushing a `Framed` write buffer holding many small frames to a
+//! loopback TCP socket at once, through `TryWrite::try_write_vectored`,
+//! against writing each block of the buffer as soon as it is full.
+
+#![feature(test)]
+
+extern crate bytes;
+extern crate test;
+extern crate tokio_proto;
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use bytes::{BlockBuf, MutBuf};
+use test::Bencher;
+use tokio_proto::io::{Framed, FlushPolicy, Readiness, SimpleParse, SimpleSerialize, Transport};
+
+const FRAMES: usize = 256;
+const FRAME_LEN: usize = 64;
+const BLOCK_SIZE: usize = 1024;
+
+// Blocking socket, the bytes written to it are drained by another thread
+struct Socket(TcpStream);
+
+impl io::Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl io::Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Readiness for Socket {
+    fn is_readable(&self) -> bool {
+        false
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+struct Never;
+
+impl SimpleParse for Never {
+    type Out = ();
+
+    fn parse(&mut self, _: &mut BlockBuf) -> Option<()> {
+        None
+    }
+}
+
+struct Raw;
+
+impl SimpleSerialize for Raw {
+    type In = &'static [u8];
+
+    fn serialize(&mut self, msg: &'static [u8], buf: &mut BlockBuf) {
+        buf.write_slice(msg);
+    }
+}
+
+// Returns a socket connected to a peer discarding everything it reads
+fn connect() -> Socket {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let (mut peer, _) = listener.accept().unwrap();
+        let mut buf = [0; 64 * 1024];
+
+        while peer.read(&mut buf).unwrap_or(0) > 0 {}
+    });
+
+    let socket = TcpStream::connect(addr).unwrap();
+    socket.set_nodelay(true).unwrap();
+
+    Socket(socket)
+}
+
+fn flush(b: &mut Bencher, policy: FlushPolicy) {
+    static FRAME: [u8; FRAME_LEN] = [b'a'; FRAME_LEN];
+
+    let wr = BlockBuf::new(FRAMES * FRAME_LEN / BLOCK_SIZE + 1, BLOCK_SIZE);
+    let mut transport = Framed::new(connect(), Never, Raw, BlockBuf::default(), wr);
+
+    transport.set_flush_policy(policy);
+    transport.set_write_water_marks(0, FRAMES * FRAME_LEN + 1);
+
+    b.iter(|| {
+        for _ in 0..FRAMES {
+            transport.write(&FRAME).unwrap();
+        }
+
+        assert_eq!(Some(()), transport.flush().unwrap());
+    });
+
+    b.bytes = (FRAMES * FRAME_LEN) as u64;
+}
+
+#[bench]
+fn flush_at_once(b: &mut Bencher) {
+    flush(b, FlushPolicy::Manual);
+}
+
+#[bench]
+fn flush_block_full(b: &mut Bencher) {
+    flush(b, FlushPolicy::BlockFull);
+}
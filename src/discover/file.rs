@@ -0,0 +1,128 @@
+use std::fs;
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use futures::{Future, Poll, BoxFuture};
+use futures::stream::Stream;
+use tokio_core::LoopHandle;
+
+use super::Change;
+
+/// Discovers endpoints by watching a file listing socket addresses.
+///
+/// The file contains one address per line. Blank lines and lines starting
+/// with `#` are ignored. The file is read again each time the poll interval
+/// elapses and the differences with the previous contents are yielded as
+/// `Change` events.
+///
+/// Lines that are not valid addresses are skipped. If the file cannot be read
+/// after it was initially loaded, the previously known endpoints are kept.
+pub struct FileList {
+    path: PathBuf,
+    interval: Duration,
+    handle: LoopHandle,
+    timer: Option<BoxFuture<(), io::Error>>,
+    current: HashSet<SocketAddr>,
+    pending: VecDeque<Change<SocketAddr>>,
+}
+
+impl FileList {
+    /// Load the file at `path` and watch it for changes, reading it again
+    /// every `interval` using a timer on the event loop referenced by
+    /// `handle`.
+    ///
+    /// The endpoints initially listed in the file are yielded as `Insert`
+    /// events.
+    pub fn new<P: AsRef<Path>>(handle: LoopHandle, path: P, interval: Duration) -> io::Result<FileList> {
+        let mut list = FileList {
+            path: path.as_ref().to_path_buf(),
+            interval: interval,
+            handle: handle,
+            timer: None,
+            current: HashSet::new(),
+            pending: VecDeque::new(),
+        };
+
+        let addrs = try!(list.read());
+        list.update(addrs);
+
+        Ok(list)
+    }
+
+    fn read(&self) -> io::Result<Vec<SocketAddr>> {
+        let mut contents = String::new();
+        try!(try!(fs::File::open(&self.path)).read_to_string(&mut contents));
+
+        let mut addrs = vec![];
+
+        for line in contents.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match line.parse() {
+                Ok(addr) => addrs.push(addr),
+                Err(_) => warn!("ignoring invalid address in {:?}; line={:?}", self.path, line),
+            }
+        }
+
+        Ok(addrs)
+    }
+
+    // Queue the changes between the current set of addresses and `addrs`
+    fn update(&mut self, addrs: Vec<SocketAddr>) {
+        let next: HashSet<SocketAddr> = addrs.iter().cloned().collect();
+
+        for addr in self.current.difference(&next) {
+            self.pending.push_back(Change::Remove(*addr));
+        }
+
+        // Addresses may be listed more than once, only insert them once while
+        // keeping the order of the file.
+        let mut seen = HashSet::new();
+
+        for addr in addrs {
+            if seen.insert(addr) && !self.current.contains(&addr) {
+                self.pending.push_back(Change::Insert(addr));
+            }
+        }
+
+        self.current = next;
+    }
+}
+
+impl Stream for FileList {
+    type Item = Change<SocketAddr>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Change<SocketAddr>>, io::Error> {
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return Poll::Ok(Some(change));
+            }
+
+            if self.timer.is_none() {
+                let timer = self.handle.clone().timeout(self.interval).flatten();
+                self.timer = Some(timer.boxed());
+            }
+
+            match self.timer.as_mut().unwrap().poll() {
+                Poll::Ok(()) => {}
+                Poll::Err(e) => return Poll::Err(e),
+                Poll::NotReady => return Poll::NotReady,
+            }
+
+            self.timer = None;
+
+            match self.read() {
+                Ok(addrs) => self.update(addrs),
+                Err(e) => warn!("failed to read {:?}; keeping endpoints; err={}", self.path, e),
+            }
+        }
+    }
+}
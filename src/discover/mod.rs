@@ -0,0 +1,49 @@
+//! Discovery of the endpoints a client connects to.
+//!
+//! A `Discover` value yields a stream of `Change` events as endpoints appear
+//! and disappear at runtime. It is consumed by `pipeline::Balance`, which
+//! opens a connection to each inserted endpoint and closes the connection to
+//! each removed endpoint.
+//!
+//! Any `Stream` of `Change` values is a `Discover`. `FileList` watches a
+//! local file listing socket addresses.
+
+mod file;
+
+pub use self::file::FileList;
+
+use std::io;
+use std::hash::Hash;
+
+use futures::Poll;
+use futures::stream::Stream;
+
+/// An endpoint discovery event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<K> {
+    /// The endpoint identified by the key became available.
+    Insert(K),
+    /// The endpoint identified by the key is no longer available.
+    Remove(K),
+}
+
+/// Yields `Change` events for a set of endpoints.
+pub trait Discover: Send + 'static {
+    /// Key identifying an endpoint, for example its address
+    type Key: Hash + Eq + Clone + Send + 'static;
+
+    /// Poll for the next change. `None` indicates that no further changes
+    /// will happen.
+    fn poll(&mut self) -> Poll<Option<Change<Self::Key>>, io::Error>;
+}
+
+impl<S, K> Discover for S
+    where S: Stream<Item = Change<K>, Error = io::Error> + Send + 'static,
+          K: Hash + Eq + Clone + Send + 'static,
+{
+    type Key = K;
+
+    fn poll(&mut self) -> Poll<Option<Change<K>>, io::Error> {
+        Stream::poll(self)
+    }
+}
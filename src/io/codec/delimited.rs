@@ -0,0 +1,363 @@
+//! Frames separated by a delimiter, such as lines.
+//!
+//! The parser searches the read buffer for the delimiter and yields the bytes
+//! preceding it, either as `Bytes` or as a UTF-8 `String`. The delimiter is
+//! not included in the frame. Bytes already searched are remembered, so
+//! receiving a long frame in many small reads does not rescan the buffer.
+//!
+//! Frames longer than the maximum length fail with a `FrameTooLarge` error.
+//! The parser then discards bytes until the next delimiter, after which the
+//! transport can be read from again.
+//!
+//! When the stream is closed, the bytes following the last delimiter are
+//! yielded as a final frame.
+
+use std::collections::VecDeque;
+use std::io;
+
+use bytes::{Buf, Bytes, BlockBuf, MutBuf};
+
+use io::{Framed, FrameTooLarge, Parse, Serialize, Stream};
+
+/// Configures the delimited parsers and serializers.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    delimiter: Vec<u8>,
+    max_length: usize,
+}
+
+/// Parses delimited frames into `Bytes` values.
+#[derive(Debug)]
+pub struct Parser {
+    search: Search,
+}
+
+/// Parses delimited frames into UTF-8 `String` values.
+#[derive(Debug)]
+pub struct StringParser {
+    search: Search,
+}
+
+/// Serializes messages followed by the delimiter.
+#[derive(Debug)]
+pub struct Serializer {
+    config: Builder,
+}
+
+/// Serializes `String` messages followed by the delimiter.
+#[derive(Debug)]
+pub struct StringSerializer {
+    inner: Serializer,
+}
+
+// Delimiter search state shared by the parsers
+#[derive(Debug)]
+struct Search {
+    config: Builder,
+    // Number of bytes, from the start of the buffer, that are known not to
+    // start a delimiter
+    searched: usize,
+    // Set when a frame was too long, until its delimiter is found
+    discarding: bool,
+}
+
+const DEFAULT_MAX_LENGTH: usize = 8 * 1024 * 1024;
+
+/*
+ *
+ * ===== impl Builder =====
+ *
+ */
+
+impl Builder {
+    /// Returns a new `Builder` for frames separated by `delimiter`.
+    ///
+    /// Frames are at most 8 MiB by default.
+    pub fn new(delimiter: &[u8]) -> Builder {
+        assert!(!delimiter.is_empty(), "delimiter must not be empty");
+
+        Builder {
+            delimiter: delimiter.to_vec(),
+            max_length: DEFAULT_MAX_LENGTH,
+        }
+    }
+
+    /// Returns a new `Builder` for frames terminated by "\n".
+    pub fn lines() -> Builder {
+        Builder::new(b"\n")
+    }
+
+    /// Returns a new `Builder` for frames terminated by "\r\n".
+    pub fn crlf() -> Builder {
+        Builder::new(b"\r\n")
+    }
+
+    /// Returns a new `Builder` for frames terminated by a NUL byte.
+    pub fn nul() -> Builder {
+        Builder::new(b"\0")
+    }
+
+    /// Set the maximum frame length, excluding the delimiter.
+    pub fn max_length(mut self, val: usize) -> Builder {
+        self.max_length = val;
+        self
+    }
+
+    /// Returns a new `Parser` using this configuration.
+    pub fn new_parser(&self) -> Parser {
+        Parser { search: Search::new(self) }
+    }
+
+    /// Returns a new `StringParser` using this configuration.
+    pub fn new_string_parser(&self) -> StringParser {
+        StringParser { search: Search::new(self) }
+    }
+
+    /// Returns a new `Serializer` using this configuration.
+    pub fn new_serializer(&self) -> Serializer {
+        Serializer { config: self.clone() }
+    }
+
+    /// Returns a new `StringSerializer` using this configuration.
+    pub fn new_string_serializer(&self) -> StringSerializer {
+        StringSerializer { inner: self.new_serializer() }
+    }
+
+    /// Frame the given stream into `Bytes` frames using this configuration.
+    ///
+    /// The maximum number of bytes buffered by the returned `Framed` is set
+    /// to fit the maximum frame length.
+    pub fn frame<T: Stream>(&self, upstream: T) -> Framed<T, Parser, Serializer> {
+        let mut framed = upstream.frame(self.new_parser(), self.new_serializer());
+        framed.set_max_buffered(self.max_buffered());
+        framed
+    }
+
+    /// Frame the given stream into `String` frames using this configuration.
+    ///
+    /// The maximum number of bytes buffered by the returned `Framed` is set
+    /// to fit the maximum frame length.
+    pub fn frame_strings<T: Stream>(&self, upstream: T) -> Framed<T, StringParser, StringSerializer> {
+        let mut framed = upstream.frame(self.new_string_parser(), self.new_string_serializer());
+        framed.set_max_buffered(self.max_buffered());
+        framed
+    }
+
+    fn max_buffered(&self) -> usize {
+        self.max_length.saturating_add(self.delimiter.len())
+    }
+}
+
+/*
+ *
+ * ===== impl Parser =====
+ *
+ */
+
+impl Parse for Parser {
+    type Out = Bytes;
+
+    fn parse(&mut self, buf: &mut BlockBuf) -> io::Result<Option<Bytes>> {
+        match try!(self.search.next(buf, false)) {
+            Some((len, delim)) => Ok(Some(take_bytes(buf, len, delim))),
+            None => Ok(None),
+        }
+    }
+
+    fn done(&mut self, buf: &mut BlockBuf) -> io::Result<Option<Bytes>> {
+        match try!(self.search.next(buf, true)) {
+            Some((len, delim)) => Ok(Some(take_bytes(buf, len, delim))),
+            None => Ok(None),
+        }
+    }
+}
+
+fn take_bytes(buf: &mut BlockBuf, len: usize, delim: usize) -> Bytes {
+    let frame = buf.shift(len);
+    buf.drop(delim);
+    frame
+}
+
+/*
+ *
+ * ===== impl StringParser =====
+ *
+ */
+
+impl Parse for StringParser {
+    type Out = String;
+
+    fn parse(&mut self, buf: &mut BlockBuf) -> io::Result<Option<String>> {
+        match try!(self.search.next(buf, false)) {
+            Some((len, delim)) => take_string(buf, len, delim).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn done(&mut self, buf: &mut BlockBuf) -> io::Result<Option<String>> {
+        match try!(self.search.next(buf, true)) {
+            Some((len, delim)) => take_string(buf, len, delim).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+fn take_string(buf: &mut BlockBuf, len: usize, delim: usize) -> io::Result<String> {
+    buf.compact();
+
+    let frame = buf.bytes().map(|bytes| bytes[..len].to_vec()).unwrap_or_else(Vec::new);
+    buf.drop(len + delim);
+
+    String::from_utf8(frame)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "frame is not valid UTF-8"))
+}
+
+/*
+ *
+ * ===== impl Serializer =====
+ *
+ */
+
+impl Serialize for Serializer {
+    type In = Vec<u8>;
+
+    fn serialize(&mut self, msg: Vec<u8>, buf: &mut BlockBuf) -> io::Result<()> {
+        let config = &self.config;
+
+        if msg.len() > config.max_length {
+            return Err(FrameTooLarge {
+                len: msg.len(),
+                max: config.max_length,
+            }.into());
+        }
+
+        if msg.windows(config.delimiter.len()).any(|w| w == &config.delimiter[..]) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "message contains the delimiter"));
+        }
+
+        buf.write_slice(&msg);
+        buf.write_slice(&config.delimiter);
+
+        Ok(())
+    }
+}
+
+/*
+ *
+ * ===== impl StringSerializer =====
+ *
+ */
+
+impl Serialize for StringSerializer {
+    type In = String;
+
+    fn serialize(&mut self, msg: String, buf: &mut BlockBuf) -> io::Result<()> {
+        self.inner.serialize(msg.into_bytes(), buf)
+    }
+}
+
+/*
+ *
+ * ===== impl Search =====
+ *
+ */
+
+impl Search {
+    fn new(config: &Builder) -> Search {
+        Search {
+            config: config.clone(),
+            searched: 0,
+            discarding: false,
+        }
+    }
+
+    // Returns the length of the next frame and of the delimiter following
+    // it. Once the stream is closed, the remaining bytes are returned as a
+    // frame without delimiter.
+    fn next(&mut self, buf: &mut BlockBuf, eof: bool) -> io::Result<Option<(usize, usize)>> {
+        let delim = self.config.delimiter.len();
+        let max = self.config.max_length;
+
+        loop {
+            let found = self.find(buf);
+
+            if self.discarding {
+                match found {
+                    Some(pos) => {
+                        trace!("discarded too long frame; remaining={}", pos);
+                        buf.drop(pos + delim);
+                        self.searched = 0;
+                        self.discarding = false;
+                        continue;
+                    }
+                    None => {
+                        let n = if eof { buf.len() } else { self.searched };
+                        buf.drop(n);
+                        self.searched = 0;
+                        return Ok(None);
+                    }
+                }
+            }
+
+            match found {
+                Some(pos) if pos > max => {
+                    buf.drop(pos + delim);
+                    self.searched = 0;
+                    return Err(FrameTooLarge { len: pos, max: max }.into());
+                }
+                Some(pos) => {
+                    self.searched = 0;
+                    return Ok(Some((pos, delim)));
+                }
+                None if self.searched > max || (eof && buf.len() > max) => {
+                    // No delimiter can follow within the maximum length,
+                    // discard the frame up to its delimiter
+                    let len = buf.len();
+                    let n = if eof { len } else { self.searched };
+
+                    debug!("frame too long; discarding until next delimiter");
+                    buf.drop(n);
+                    self.searched = 0;
+                    self.discarding = !eof;
+
+                    return Err(FrameTooLarge { len: len, max: max }.into());
+                }
+                None if eof && !buf.is_empty() => {
+                    self.searched = 0;
+                    return Ok(Some((buf.len(), 0)));
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+
+    // Returns the position of the first delimiter in the buffer, searching
+    // from where the previous call left off.
+    fn find(&mut self, buf: &BlockBuf) -> Option<usize> {
+        let delimiter = &self.config.delimiter;
+        let mut cursor = buf.buf();
+        cursor.advance(self.searched);
+
+        // Position of the first byte of the window in the buffer
+        let mut pos = self.searched;
+        let mut window = VecDeque::with_capacity(delimiter.len());
+
+        while let Some(byte) = cursor.read_byte() {
+            window.push_back(byte);
+
+            if window.len() > delimiter.len() {
+                window.pop_front();
+                pos += 1;
+            }
+
+            if window.len() == delimiter.len() && window.iter().eq(delimiter.iter()) {
+                return Some(pos);
+            }
+        }
+
+        // A delimiter may still start in the last, partial, window
+        self.searched = if window.len() == delimiter.len() { pos + 1 } else { pos };
+
+        None
+    }
+}
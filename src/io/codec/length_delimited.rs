@@ -0,0 +1,454 @@
+//! Frames prefixed by their length.
+//!
+//! A frame is made of an optional fixed size prefix, a length field, then the
+//! frame payload:
+//!
+//! ```text
+//! +------------+--------------+---------------------+
+//! | offset     | length field | payload             |
+//! | (N bytes)  | (1-8 bytes)  | (length + adjust)   |
+//! +------------+--------------+---------------------+
+//! ```
+//!
+//! The length field is 1, 2, 4 or 8 bytes wide in either byte order, or a
+//! variable length integer. Its value, plus the length adjustment, is the
+//! number of bytes following the length field. The adjustment is useful for
+//! protocols where the length includes the header, or excludes a trailer.
+//!
+//! By default, the parser strips the prefix and the length field and yields
+//! the payload. The serializer writes the length field in front of each
+//! message, after the first `length_field_offset` bytes of the message.
+
+use std::io;
+
+use bytes::{Buf, Bytes, BlockBuf, MutBuf};
+
+use io::{Framed, FrameTooLarge, Parse, Serialize, Stream};
+
+/// Configures the length-delimited `Parser` and `Serializer`.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    length_field: LengthField,
+    endianness: Endianness,
+    length_field_offset: usize,
+    length_adjustment: isize,
+    strip_header: bool,
+    max_frame_length: usize,
+}
+
+/// Width and encoding of the length field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthField {
+    /// One byte
+    U8,
+    /// Two bytes
+    U16,
+    /// Four bytes
+    U32,
+    /// Eight bytes
+    U64,
+    /// Unsigned LEB128 variable length integer, as used by protocol buffers,
+    /// of at most 10 bytes. The byte order is ignored.
+    Varint,
+}
+
+/// Byte order of the length field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    /// Most significant byte first, aka network byte order
+    Big,
+    /// Least significant byte first
+    Little,
+}
+
+/// Parses length-delimited frames into `Bytes` values.
+#[derive(Debug)]
+pub struct Parser {
+    config: Builder,
+    // Header length and payload length of the frame being parsed, once its
+    // header was read
+    head: Option<(usize, usize)>,
+}
+
+/// Serializes messages into length-delimited frames.
+#[derive(Debug)]
+pub struct Serializer {
+    config: Builder,
+}
+
+const DEFAULT_MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+// Maximum length of a varint encoded u64
+const MAX_VARINT_LEN: usize = 10;
+
+/*
+ *
+ * ===== impl Builder =====
+ *
+ */
+
+impl Builder {
+    /// Returns a new `Builder` with the default configuration.
+    ///
+    /// By default, frames start with a 4 byte big endian length field
+    /// counting the payload bytes, the header is stripped and frames are at
+    /// most 8 MiB.
+    pub fn new() -> Builder {
+        Builder {
+            length_field: LengthField::U32,
+            endianness: Endianness::Big,
+            length_field_offset: 0,
+            length_adjustment: 0,
+            strip_header: true,
+            max_frame_length: DEFAULT_MAX_FRAME_LENGTH,
+        }
+    }
+
+    /// Set the width and encoding of the length field.
+    pub fn length_field(mut self, val: LengthField) -> Builder {
+        self.length_field = val;
+        self
+    }
+
+    /// Set the byte order of the length field.
+    pub fn endianness(mut self, val: Endianness) -> Builder {
+        self.endianness = val;
+        self
+    }
+
+    /// Set the number of bytes preceding the length field.
+    pub fn length_field_offset(mut self, val: usize) -> Builder {
+        self.length_field_offset = val;
+        self
+    }
+
+    /// Set the value added to the length field to compute the number of
+    /// bytes following it.
+    pub fn length_adjustment(mut self, val: isize) -> Builder {
+        self.length_adjustment = val;
+        self
+    }
+
+    /// Set whether the parser strips the bytes up to and including the
+    /// length field, yielding only the payload.
+    pub fn strip_header(mut self, val: bool) -> Builder {
+        self.strip_header = val;
+        self
+    }
+
+    /// Set the maximum payload length. Longer frames fail with a
+    /// `FrameTooLarge` error as soon as their header is read, whether parsed
+    /// or serialized.
+    pub fn max_frame_length(mut self, val: usize) -> Builder {
+        self.max_frame_length = val;
+        self
+    }
+
+    /// Returns a new `Parser` using this configuration.
+    pub fn new_parser(&self) -> Parser {
+        Parser {
+            config: self.clone(),
+            head: None,
+        }
+    }
+
+    /// Returns a new `Serializer` using this configuration.
+    pub fn new_serializer(&self) -> Serializer {
+        Serializer { config: self.clone() }
+    }
+
+    /// Frame the given stream using this configuration.
+    ///
+    /// The maximum number of bytes buffered by the returned `Framed` is set
+    /// to fit the maximum frame length.
+    pub fn frame<T: Stream>(&self, upstream: T) -> Framed<T, Parser, Serializer> {
+        let mut framed = upstream.frame(self.new_parser(), self.new_serializer());
+
+        let max = self.length_field_offset + self.length_field.max_len() + self.max_frame_length;
+        framed.set_max_buffered(max);
+
+        framed
+    }
+
+    // Header length and payload length of the frame at the start of `buf`,
+    // if the header was fully received.
+    fn read_head(&self, buf: &BlockBuf) -> io::Result<Option<(usize, usize)>> {
+        let mut cursor = buf.buf();
+
+        if cursor.remaining() < self.length_field_offset {
+            return Ok(None);
+        }
+
+        cursor.advance(self.length_field_offset);
+
+        let (len, field_len) = match self.length_field.width() {
+            Some(width) => {
+                if cursor.remaining() < width {
+                    return Ok(None);
+                }
+
+                let mut bytes = [0; 8];
+
+                for byte in bytes[..width].iter_mut() {
+                    *byte = cursor.read_byte().unwrap();
+                }
+
+                (self.endianness.read(&bytes[..width]), width)
+            }
+            None => {
+                match try!(read_varint(&mut cursor)) {
+                    Some(v) => v,
+                    None => return Ok(None),
+                }
+            }
+        };
+
+        let payload = match add_signed(len, self.length_adjustment) {
+            Some(payload) if payload <= usize::max_value() as u64 => payload as usize,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid frame length")),
+        };
+
+        if payload > self.max_frame_length {
+            return Err(FrameTooLarge {
+                len: payload,
+                max: self.max_frame_length,
+            }.into());
+        }
+
+        Ok(Some((self.length_field_offset + field_len, payload)))
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+/*
+ *
+ * ===== impl Parser =====
+ *
+ */
+
+impl Parser {
+    /// Returns a new `Parser` with the default configuration.
+    pub fn new() -> Parser {
+        Builder::new().new_parser()
+    }
+}
+
+impl Parse for Parser {
+    type Out = Bytes;
+
+    fn parse(&mut self, buf: &mut BlockBuf) -> io::Result<Option<Bytes>> {
+        let (head, payload) = match self.head {
+            Some(head) => head,
+            None => {
+                match try!(self.config.read_head(buf)) {
+                    Some(head) => head,
+                    None => return Ok(None),
+                }
+            }
+        };
+
+        if buf.len() < head + payload {
+            // Remember the header until the frame is complete
+            self.head = Some((head, payload));
+            return Ok(None);
+        }
+
+        self.head = None;
+
+        if self.config.strip_header {
+            buf.drop(head);
+            return Ok(Some(buf.shift(payload)));
+        }
+
+        Ok(Some(buf.shift(head + payload)))
+    }
+
+    fn frame_len(&self, _: &BlockBuf) -> Option<usize> {
+        self.head.map(|(head, payload)| head + payload)
+    }
+}
+
+/*
+ *
+ * ===== impl Serializer =====
+ *
+ */
+
+impl Serializer {
+    /// Returns a new `Serializer` with the default configuration.
+    pub fn new() -> Serializer {
+        Builder::new().new_serializer()
+    }
+}
+
+impl Serialize for Serializer {
+    type In = Vec<u8>;
+
+    fn serialize(&mut self, msg: Vec<u8>, buf: &mut BlockBuf) -> io::Result<()> {
+        let config = &self.config;
+
+        if msg.len() < config.length_field_offset {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "message shorter than length field offset"));
+        }
+
+        let (prefix, payload) = msg.split_at(config.length_field_offset);
+
+        if payload.len() > config.max_frame_length {
+            return Err(FrameTooLarge {
+                len: payload.len(),
+                max: config.max_frame_length,
+            }.into());
+        }
+
+        let len = match sub_signed(payload.len() as u64, config.length_adjustment) {
+            Some(len) if config.length_field.fits(len) => len,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame length does not fit the length field")),
+        };
+
+        let mut head = [0; MAX_VARINT_LEN];
+
+        let field_len = match config.length_field.width() {
+            Some(width) => {
+                config.endianness.write(len, &mut head[..width]);
+                width
+            }
+            None => write_varint(len, &mut head),
+        };
+
+        buf.write_slice(prefix);
+        buf.write_slice(&head[..field_len]);
+        buf.write_slice(payload);
+
+        Ok(())
+    }
+}
+
+/*
+ *
+ * ===== impl LengthField =====
+ *
+ */
+
+impl LengthField {
+    // Width in bytes of fixed width fields
+    fn width(&self) -> Option<usize> {
+        match *self {
+            LengthField::U8 => Some(1),
+            LengthField::U16 => Some(2),
+            LengthField::U32 => Some(4),
+            LengthField::U64 => Some(8),
+            LengthField::Varint => None,
+        }
+    }
+
+    fn max_len(&self) -> usize {
+        self.width().unwrap_or(MAX_VARINT_LEN)
+    }
+
+    // Returns true if the value can be represented by the field
+    fn fits(&self, val: u64) -> bool {
+        match self.width() {
+            Some(8) | None => true,
+            Some(width) => val < 1 << (width * 8),
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl Endianness =====
+ *
+ */
+
+impl Endianness {
+    fn read(&self, bytes: &[u8]) -> u64 {
+        let fold = |acc: u64, byte: &u8| (acc << 8) | *byte as u64;
+
+        match *self {
+            Endianness::Big => bytes.iter().fold(0, fold),
+            Endianness::Little => bytes.iter().rev().fold(0, fold),
+        }
+    }
+
+    fn write(&self, mut val: u64, dst: &mut [u8]) {
+        let len = dst.len();
+
+        for i in 0..len {
+            let byte = (val & 0xff) as u8;
+            val >>= 8;
+
+            match *self {
+                Endianness::Big => dst[len - 1 - i] = byte,
+                Endianness::Little => dst[i] = byte,
+            }
+        }
+    }
+}
+
+// Returns `len + adjustment`, or `None` if it overflows or is negative.
+fn add_signed(len: u64, adjustment: isize) -> Option<u64> {
+    if adjustment < 0 {
+        // `wrapping_neg` keeps `isize::MIN`, whose magnitude is then
+        // recovered by the cast.
+        len.checked_sub(adjustment.wrapping_neg() as u64)
+    } else {
+        len.checked_add(adjustment as u64)
+    }
+}
+
+// Returns `len - adjustment`, or `None` if it overflows or is negative.
+fn sub_signed(len: u64, adjustment: isize) -> Option<u64> {
+    if adjustment < 0 {
+        len.checked_add(adjustment.wrapping_neg() as u64)
+    } else {
+        len.checked_sub(adjustment as u64)
+    }
+}
+
+// Returns the decoded value and its length, or `None` if incomplete.
+fn read_varint<B: Buf>(buf: &mut B) -> io::Result<Option<(u64, usize)>> {
+    let mut val = 0;
+
+    for i in 0..MAX_VARINT_LEN {
+        let byte = match buf.read_byte() {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+
+        // The last byte only holds the most significant bit of the value
+        if i == MAX_VARINT_LEN - 1 && byte > 1 {
+            break;
+        }
+
+        val |= ((byte & 0x7f) as u64) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            return Ok(Some((val, i + 1)));
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::InvalidData, "invalid varint length field"))
+}
+
+// Returns the number of bytes written.
+fn write_varint(mut val: u64, dst: &mut [u8]) -> usize {
+    let mut i = 0;
+
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+
+        if val == 0 {
+            dst[i] = byte;
+            return i + 1;
+        }
+
+        dst[i] = byte | 0x80;
+        i += 1;
+    }
+}
@@ -0,0 +1,132 @@
+//! The `Codec` trait and `Parse` / `Serialize` implementations for common
+//! framing schemes.
+//!
+//! Each codec yields its frames out of the read buffer using
+//! `BlockBuf::shift`, without copying them.
+//!
+//! `Framed` holds independent `Parse` and `Serialize` values. Protocols
+//! needing state shared by both directions, for example a negotiated protocol
+//! version or a compression context, implement `Codec` instead and frame a
+//! stream with `Stream::framed`.
+
+pub mod delimited;
+pub mod length_delimited;
+
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use bytes::BlockBuf;
+
+use io::{Parse, Serialize};
+
+/// Decodes and encodes frames using a single value.
+pub trait Codec {
+
+    /// Frames decoded from the read buffer
+    type Out;
+
+    /// Frames encoded into the write buffer
+    type In;
+
+    /// Decode a frame from the start of the buffer, consuming its bytes. See
+    /// `Parse::parse`.
+    fn decode(&mut self, buf: &mut BlockBuf) -> io::Result<Option<Self::Out>>;
+
+    /// Called when there are no more inbound bytes. See `Parse::done`.
+    fn decode_eof(&mut self, _buf: &mut BlockBuf) -> io::Result<Option<Self::Out>> {
+        Ok(None)
+    }
+
+    /// Returns the total length of the frame at the start of the buffer, if
+    /// it is known before the frame is complete. See `Parse::frame_len`.
+    fn frame_len(&self, _buf: &BlockBuf) -> Option<usize> {
+        None
+    }
+
+    /// Encode a frame into the buffer. See `Serialize::serialize`.
+    fn encode(&mut self, msg: Self::In, buf: &mut BlockBuf) -> io::Result<()>;
+}
+
+/// The decoding half of a `Codec`, see `split`.
+pub struct CodecParser<C> {
+    codec: Arc<Mutex<C>>,
+}
+
+/// The encoding half of a `Codec`, see `split`.
+pub struct CodecSerializer<C> {
+    codec: Arc<Mutex<C>>,
+}
+
+/// Split a `Codec` into a `Parse` and a `Serialize` value sharing it.
+///
+/// Both halves are `Send` as long as the codec is, so a transport framed
+/// with them may be moved to another thread.
+pub fn split<C: Codec>(codec: C) -> (CodecParser<C>, CodecSerializer<C>) {
+    let codec = Arc::new(Mutex::new(codec));
+
+    (CodecParser { codec: codec.clone() }, CodecSerializer { codec: codec })
+}
+
+/*
+ *
+ * ===== impl Codec =====
+ *
+ */
+
+/// A `Parse` and `Serialize` pair is a `Codec`.
+impl<P: Parse, S: Serialize> Codec for (P, S) {
+    type Out = P::Out;
+    type In = S::In;
+
+    fn decode(&mut self, buf: &mut BlockBuf) -> io::Result<Option<P::Out>> {
+        self.0.parse(buf)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BlockBuf) -> io::Result<Option<P::Out>> {
+        self.0.done(buf)
+    }
+
+    fn frame_len(&self, buf: &BlockBuf) -> Option<usize> {
+        self.0.frame_len(buf)
+    }
+
+    fn encode(&mut self, msg: S::In, buf: &mut BlockBuf) -> io::Result<()> {
+        self.1.serialize(msg, buf)
+    }
+}
+
+/*
+ *
+ * ===== impl CodecParser =====
+ *
+ */
+
+impl<C: Codec> Parse for CodecParser<C> {
+    type Out = C::Out;
+
+    fn parse(&mut self, buf: &mut BlockBuf) -> io::Result<Option<C::Out>> {
+        self.codec.lock().unwrap().decode(buf)
+    }
+
+    fn done(&mut self, buf: &mut BlockBuf) -> io::Result<Option<C::Out>> {
+        self.codec.lock().unwrap().decode_eof(buf)
+    }
+
+    fn frame_len(&self, buf: &BlockBuf) -> Option<usize> {
+        self.codec.lock().unwrap().frame_len(buf)
+    }
+}
+
+/*
+ *
+ * ===== impl CodecSerializer =====
+ *
+ */
+
+impl<C: Codec> Serialize for CodecSerializer<C> {
+    type In = C::In;
+
+    fn serialize(&mut self, msg: C::In, buf: &mut BlockBuf) -> io::Result<()> {
+        self.codec.lock().unwrap().encode(msg, buf)
+    }
+}
@@ -1,8 +1,8 @@
 #![allow(warnings)]
 
 use io::{Readiness, Stream, Transport, TryRead, TryWrite};
-use bytes::{alloc, MutBuf, BlockBuf, Source};
-use std::io;
+use bytes::{alloc, Buf, MutBuf, BlockBuf, BlockBufCursor, Source};
+use std::{error, fmt, io};
 
 /// Transport handling frame encoding and decoding.
 pub struct Framed<T, P, S> {
@@ -11,33 +11,155 @@ pub struct Framed<T, P, S> {
     serialize: S,
     // Set to true until `parse` returns `None`
     is_readable: bool,
+    // Set to true once the upstream reached EOF
+    eof: bool,
     // Read buffer
     rd: BlockBuf,
     // Write buffer
     wr: BlockBuf,
+    // Stop accepting writes once the write buffer holds this many bytes
+    wr_high: usize,
+    // Accept writes again once the write buffer is drained to this many bytes
+    wr_low: usize,
+    // Set to true when the write buffer reached the high water mark
+    wr_blocked: bool,
+    // Maximum number of bytes buffered while waiting for a frame
+    max_buffered: usize,
+    // When buffered writes are written to the upstream
+    flush_policy: FlushPolicy,
 }
 
+/// The components of a `Framed` transport, see `Framed::into_parts`.
+pub struct Parts<T, P, S> {
+    /// The upstream stream
+    pub upstream: T,
+    /// The frame parser
+    pub parse: P,
+    /// The frame serializer
+    pub serialize: S,
+    /// Bytes read from the upstream but not yet parsed
+    pub rd: BlockBuf,
+    /// Serialized frames not yet written to the upstream
+    pub wr: BlockBuf,
+}
+
+/// Determines when `Framed` writes buffered frames to the upstream without
+/// waiting for `flush` to be called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Flush after every frame written.
+    Always,
+    /// Write each block of the write buffer as soon as it is full. This is
+    /// the default.
+    BlockFull,
+    /// Only write buffered frames when `flush` is called.
+    Manual,
+}
+
+/// Error returned by `Framed` when a frame exceeds the maximum number of
+/// buffered bytes.
+///
+/// It is wrapped in an `io::Error` of kind `InvalidData` and may be retrieved
+/// with `io::Error::get_ref`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTooLarge {
+    /// Length of the frame if reported by the parser, otherwise the number
+    /// of buffered bytes
+    pub len: usize,
+    /// Maximum number of buffered bytes
+    pub max: usize,
+}
+
+const DEFAULT_WRITE_HIGH_WATER_MARK: usize = 64 * 1024;
+const DEFAULT_WRITE_LOW_WATER_MARK: usize = 16 * 1024;
+const DEFAULT_MAX_BUFFERED: usize = 8 * 1024 * 1024;
+
+// Maximum number of blocks handed to a single vectored write
+const MAX_WRITE_BLOCKS: usize = 64;
+
 /// Parses frames out of a `BlockBuf`
+///
+/// Parsing may fail, for example on malformed input, in which case reading
+/// from the `Framed` transport fails with the returned error. Parsers that
+/// never fail may implement `SimpleParse` instead.
 pub trait Parse {
 
     /// Parse result
     type Out;
 
     /// Optionally parse a frame from the given buffer.
-    fn parse(&mut self, buf: &mut BlockBuf) -> Option<Self::Out>;
+    fn parse(&mut self, buf: &mut BlockBuf) -> io::Result<Option<Self::Out>>;
 
     /// Called when there are no more inbound bytes
-    fn done(&mut self, buf: &mut BlockBuf) -> Option<Self::Out> {
+    ///
+    /// `done` is called again on each read until it returns `None`, which
+    /// allows yielding multiple trailing frames. If bytes remain in the
+    /// buffer at that point, the read fails with an `UnexpectedEof` error.
+    /// Parsers accepting truncated input should consume the remaining bytes.
+    fn done(&mut self, buf: &mut BlockBuf) -> io::Result<Option<Self::Out>> {
+        Ok(None)
+    }
+
+    /// Returns the total length of the frame at the start of the buffer, if
+    /// it is known before the frame is complete.
+    ///
+    /// Called after `parse` returned `None`. Reporting the length, for
+    /// example from a length prefix, lets `Framed` reject a frame exceeding
+    /// the maximum number of buffered bytes without buffering it first.
+    fn frame_len(&self, buf: &BlockBuf) -> Option<usize> {
         None
     }
 }
 
 /// Serialize frames into a `BlockBuf`
+///
+/// Serializing may fail, in which case writing to the `Framed` transport
+/// fails with the returned error. Implementations should not write to the
+/// buffer before failing. Serializers that never fail may implement
+/// `SimpleSerialize` instead.
 pub trait Serialize {
 
     /// Type to serialize
     type In;
 
+    /// Serialize the frame into the `BlockBuf`
+    fn serialize(&mut self, msg: Self::In, buf: &mut BlockBuf) -> io::Result<()>;
+}
+
+/// A `Parse` that never fails.
+///
+/// This is the signature `Parse` had before parsing was made fallible.
+/// Existing parsers may keep it by implementing this trait instead, as every
+/// `SimpleParse` is a `Parse`.
+pub trait SimpleParse {
+
+    /// Parse result
+    type Out;
+
+    /// Optionally parse a frame from the given buffer.
+    fn parse(&mut self, buf: &mut BlockBuf) -> Option<Self::Out>;
+
+    /// Called when there are no more inbound bytes. See `Parse::done`.
+    fn done(&mut self, buf: &mut BlockBuf) -> Option<Self::Out> {
+        None
+    }
+
+    /// Returns the total length of the frame at the start of the buffer, if
+    /// it is known before the frame is complete. See `Parse::frame_len`.
+    fn frame_len(&self, buf: &BlockBuf) -> Option<usize> {
+        None
+    }
+}
+
+/// A `Serialize` that never fails.
+///
+/// This is the signature `Serialize` had before serializing was made
+/// fallible. Every `SimpleSerialize` is a `Serialize`.
+pub trait SimpleSerialize {
+
+    /// Type to serialize
+    type In;
+
     /// Serialize the frame into the `BlockBuf`
     fn serialize(&mut self, msg: Self::In, buf: &mut BlockBuf);
 }
@@ -60,8 +182,184 @@ impl<T, P, S> Framed<T, P, S>
             parse: parse,
             serialize: serialize,
             is_readable: false,
+            eof: false,
             rd: rd,
             wr: wr,
+            wr_high: DEFAULT_WRITE_HIGH_WATER_MARK,
+            wr_low: DEFAULT_WRITE_LOW_WATER_MARK,
+            wr_blocked: false,
+            max_buffered: DEFAULT_MAX_BUFFERED,
+            flush_policy: FlushPolicy::BlockFull,
+        }
+    }
+
+    /// Create a new `Framed` from its parts.
+    ///
+    /// Bytes left in the read buffer are parsed on the next read, and bytes
+    /// left in the write buffer are written on the next flush. The transport
+    /// options are reset to their defaults.
+    pub fn from_parts(parts: Parts<T, P, S>) -> Framed<T, P, S> {
+        let mut framed = Framed::new(parts.upstream,
+                                     parts.parse,
+                                     parts.serialize,
+                                     parts.rd,
+                                     parts.wr);
+
+        // Buffered bytes may already hold frames
+        framed.is_readable = !framed.rd.is_empty();
+        framed.update_writable();
+        framed
+    }
+
+    /// Consume the `Framed`, returning its parts.
+    ///
+    /// This allows changing the framing of a connection, for example after a
+    /// protocol upgrade, without losing buffered bytes: the read buffer holds
+    /// the bytes received after the last parsed frame.
+    pub fn into_parts(self) -> Parts<T, P, S> {
+        Parts {
+            upstream: self.upstream,
+            parse: self.parse,
+            serialize: self.serialize,
+            rd: self.rd,
+            wr: self.wr,
+        }
+    }
+
+    /// Returns a reference to the upstream stream.
+    pub fn get_ref(&self) -> &T {
+        &self.upstream
+    }
+
+    /// Returns a mutable reference to the upstream stream.
+    ///
+    /// Reading from or writing to the upstream directly may corrupt the
+    /// framed byte stream.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.upstream
+    }
+
+    /// Set when buffered frames are written to the upstream.
+    pub fn set_flush_policy(&mut self, val: FlushPolicy) {
+        self.flush_policy = val;
+    }
+
+    /// Set the maximum number of bytes buffered while waiting for a complete
+    /// frame. Defaults to 8 MiB.
+    ///
+    /// Reading fails with a `FrameTooLarge` error once more bytes are
+    /// buffered, or as soon as the parser reports a longer frame length.
+    pub fn set_max_buffered(&mut self, val: usize) {
+        self.max_buffered = val;
+    }
+
+    // Fail if the incomplete frame at the start of the read buffer exceeds
+    // the limit.
+    fn check_frame_len(&self) -> io::Result<()> {
+        let len = self.parse.frame_len(&self.rd).unwrap_or(self.rd.len());
+
+        if len > self.max_buffered {
+            debug!("frame too large; len={}; max={}", len, self.max_buffered);
+
+            return Err(FrameTooLarge {
+                len: len,
+                max: self.max_buffered,
+            }.into());
+        }
+
+        Ok(())
+    }
+
+    /// Set the write buffer water marks.
+    ///
+    /// Once the write buffer holds `high` bytes or more, the transport stops
+    /// being writable. It becomes writable again once flushing drained the
+    /// write buffer to `low` bytes or less. Defaults to 64 KiB and 16 KiB.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `low` is greater than `high`.
+    pub fn set_write_water_marks(&mut self, low: usize, high: usize) {
+        assert!(low <= high, "low water mark must not exceed high water mark");
+
+        self.wr_low = low;
+        self.wr_high = high;
+        self.update_writable();
+    }
+
+    // Parse the trailing frames once the upstream reached EOF
+    fn read_done(&mut self) -> io::Result<Option<P::Out>> {
+        if let Some(frame) = try!(self.parse.done(&mut self.rd)) {
+            self.is_readable = true;
+            return Ok(Some(frame));
+        }
+
+        self.is_readable = false;
+
+        if !self.rd.is_empty() {
+            debug!("stream closed with partial frame; buffered={}", self.rd.len());
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream closed with partial frame"));
+        }
+
+        Ok(None)
+    }
+
+    // Write the write buffer to the upstream. Unless `all` is set, the last
+    // block, which may still be filling up, is kept buffered. Returns false
+    // if the upstream would block.
+    //
+    // When flushing several blocks, they are handed to the upstream at once
+    // using a vectored write instead of one write per block. Writes made
+    // while frames are being written only ever write the leading block, so
+    // they do not need to gather the blocks.
+    fn write_buffered(&mut self, all: bool) -> io::Result<bool> {
+        loop {
+            if self.wr.is_empty() {
+                return Ok(true);
+            }
+
+            let block = self.wr.buf().bytes().len();
+
+            // Only the block being filled is left
+            if !all && block == self.wr.len() {
+                return Ok(true);
+            }
+
+            trace!("writing; remaining={:?}", self.wr.len());
+
+            let res = if all && block < self.wr.len() {
+                let blocks = block_cursors(&self.wr, MAX_WRITE_BLOCKS);
+                let bufs: Vec<&[u8]> = blocks.iter().map(|block| block.bytes()).collect();
+
+                self.upstream.try_write_vectored(&bufs)
+            } else {
+                self.upstream.try_write_buf(&mut self.wr.buf())
+            };
+
+            match res {
+                Ok(Some(n)) => {
+                    self.wr.drop(n);
+                    self.update_writable();
+                }
+                Ok(None) => return Ok(false),
+                Err(e) => {
+                    trace!("framed transport write error; err={:?}", e);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    // Update `wr_blocked` according to the current write buffer length.
+    fn update_writable(&mut self) {
+        let len = self.wr.len();
+
+        if !self.wr_blocked && len >= self.wr_high {
+            trace!("write buffer reached high water mark; len={}", len);
+            self.wr_blocked = true;
+        } else if self.wr_blocked && len <= self.wr_low {
+            trace!("write buffer drained to low water mark; len={}", len);
+            self.wr_blocked = false;
         }
     }
 }
@@ -75,19 +373,25 @@ impl<T, P, S> Transport for Framed<T, P, S>
     type Out = P::Out;
 
     fn read(&mut self) -> io::Result<Option<Self::Out>> {
+        if self.eof {
+            return self.read_done();
+        }
+
         loop {
             // If the read buffer has any pending data, then it could be
             // possible that `parse` will return a new frame. We leave it to
             // the parser to optimize detecting that more data is required.
             if !self.rd.is_empty()  {
                 trace!("read buffer has data");
-                if let Some(frame) = self.parse.parse(&mut self.rd) {
+                if let Some(frame) = try!(self.parse.parse(&mut self.rd)) {
                     trace!("frame parsed from buffer");
                     self.is_readable = true;
                     return Ok(Some(frame));
                 }
 
                 self.is_readable = false;
+
+                try!(self.check_frame_len());
             }
 
             assert!(self.rd.remaining() > 0);
@@ -96,7 +400,8 @@ impl<T, P, S> Transport for Framed<T, P, S>
             match try!(self.upstream.try_read_buf(&mut self.rd)) {
                 Some(0) => {
                     trace!("read 0 bytes");
-                    return Ok(self.parse.done(&mut self.rd));
+                    self.eof = true;
+                    return self.read_done();
                 }
                 Some(_) => {}
                 None => {
@@ -113,14 +418,19 @@ impl<T, P, S> Transport for Framed<T, P, S>
         }
 
         // Serialize the msg
-        self.serialize.serialize(msg, &mut self.wr);
+        try!(self.serialize.serialize(msg, &mut self.wr));
+        self.update_writable();
 
-        // Writing to the socket until flush. This allows buffering up more
-        // data and reducing the number of syscalls made.
-        //
-        // TODO: A better solution may be to wait until a block in the
-        // `BlockBuf` is full and then flush that block immediately.
-        Ok(None)
+        match self.flush_policy {
+            FlushPolicy::Always => self.flush(),
+            FlushPolicy::BlockFull => {
+                // Write full blocks right away, keeping the memory used by
+                // the write buffer down, and buffer the rest until flush.
+                try!(self.write_buffered(false));
+                Ok(None)
+            }
+            FlushPolicy::Manual => Ok(None),
+        }
     }
 
     fn flush(&mut self) -> io::Result<Option<()>> {
@@ -129,24 +439,30 @@ impl<T, P, S> Transport for Framed<T, P, S>
 
         trace!("flushing framed transport");
 
-        loop {
-            if self.wr.is_empty() {
-                trace!("framed transport flushed");
-                return Ok(Some(()));
-            }
+        if !try!(self.write_buffered(true)) {
+            return Ok(None);
+        }
 
-            trace!("writing; remaining={:?}", self.wr.len());
+        trace!("framed transport flushed");
+        Ok(Some(()))
+    }
+}
 
-            match self.upstream.try_write_buf(&mut self.wr.buf()) {
-                Ok(Some(n)) => self.wr.drop(n),
-                Ok(None) => return Ok(None),
-                Err(e) => {
-                    trace!("framed transport flush error; err={:?}", e);
-                    return Err(e);
-                }
-            }
-        }
+// Returns cursors positioned at the start of each of the first `max` blocks
+// holding buffered bytes.
+fn block_cursors(buf: &BlockBuf, max: usize) -> Vec<BlockBufCursor> {
+    let mut blocks = Vec::with_capacity(max);
+    let mut pos = 0;
+
+    while pos < buf.len() && blocks.len() < max {
+        let mut cursor = buf.buf();
+        cursor.advance(pos);
+
+        pos += cursor.bytes().len();
+        blocks.push(cursor);
     }
+
+    blocks
 }
 
 impl<T, P, S> Readiness for Framed<T, P, S>
@@ -157,11 +473,70 @@ impl<T, P, S> Readiness for Framed<T, P, S>
     }
 
     fn is_writable(&self) -> bool {
-        // Always accept writes and let the write buffer grow
-        //
-        // TODO: This may not be the best option for robustness, but for now it
-        // makes the microbenchmarks happy.
-        true
+        // Accept writes until the write buffer reaches the high water mark,
+        // leaving it to `flush` to drain it.
+        !self.wr_blocked
+    }
+
+}
+
+/*
+ *
+ * ===== impl SimpleParse =====
+ *
+ */
+
+impl<P: SimpleParse> Parse for P {
+    type Out = P::Out;
+
+    fn parse(&mut self, buf: &mut BlockBuf) -> io::Result<Option<P::Out>> {
+        Ok(SimpleParse::parse(self, buf))
+    }
+
+    fn done(&mut self, buf: &mut BlockBuf) -> io::Result<Option<P::Out>> {
+        Ok(SimpleParse::done(self, buf))
     }
 
+    fn frame_len(&self, buf: &BlockBuf) -> Option<usize> {
+        SimpleParse::frame_len(self, buf)
+    }
+}
+
+/*
+ *
+ * ===== impl SimpleSerialize =====
+ *
+ */
+
+impl<S: SimpleSerialize> Serialize for S {
+    type In = S::In;
+
+    fn serialize(&mut self, msg: S::In, buf: &mut BlockBuf) -> io::Result<()> {
+        SimpleSerialize::serialize(self, msg, buf);
+        Ok(())
+    }
+}
+
+/*
+ *
+ * ===== impl FrameTooLarge =====
+ *
+ */
+
+impl fmt::Display for FrameTooLarge {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "frame too large; len={}; max={}", self.len, self.max)
+    }
+}
+
+impl error::Error for FrameTooLarge {
+    fn description(&self) -> &str {
+        "frame too large"
+    }
+}
+
+impl From<FrameTooLarge> for io::Error {
+    fn from(err: FrameTooLarge) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
 }
@@ -56,6 +56,14 @@
 //!             if let Some(line) = parse_new_line(&mut self.rd) {
 //!                 return Ok(Some(line));
 //!             }
+//!
+//!             // Otherwise, read more data from the source
+//!             let mut chunk = [0; 1024];
+//!
+//!             match try!(self.source.try_read(&mut chunk)) {
+//!                 Some(0) | None => return Ok(None),
+//!                 Some(n) => self.rd.extend_from_slice(&chunk[..n]),
+//!             }
 //!         }
 //!     }
 //!
@@ -93,21 +101,47 @@
 //!     // Search for "\n", if found, read everything from the start of the
 //!     // buffer to the newline as a string then shift all bytes after the
 //!     // newline to the beginning of the buffer.
-//!     unimplemented!();
+//!     let pos = match buf.iter().position(|b| *b == b'\n') {
+//!         Some(pos) => pos,
+//!         None => return None,
+//!     };
+//!
+//!     let line = String::from_utf8_lossy(&buf[..pos]).into_owned();
+//!     shift(buf, pos + 1);
+//!
+//!     Some(line)
 //! }
 //!
 //! fn shift(buf: &mut Vec<u8>, n: usize) {
 //!     // Drop the first `n` bytes in the buffer and move all bytes after that
 //!     // point to the front of the buffer.
+//!     buf.drain(..n);
 //! }
 //! ```
+//!
+//! In practice, such a transport does not need to be written by hand. `Framed`
+//! adapts a stream into a transport given a `Parse` and a `Serialize`
+//! implementation, and the `codec` module provides implementations for common
+//! framing schemes. The transport above is equivalent to:
+//!
+//! ```rust,no_run
+//! # use tokio_proto::io::Stream;
+//! use tokio_proto::io::codec::delimited;
+//!
+//! # fn line_transport<T: Stream>(source: T) {
+//! let transport = delimited::Builder::lines().frame_strings(source);
+//! # }
+//! ```
+
+pub mod codec;
 
 mod framing;
 mod ready;
 mod stream;
 mod transport;
 
-pub use self::framing::{Framed, Parse, Serialize};
+pub use self::codec::Codec;
+pub use self::framing::{Framed, FlushPolicy, FrameTooLarge, Parts, Parse, Serialize, SimpleParse, SimpleSerialize};
 pub use self::ready::{Readiness, Ready};
 pub use self::stream::Stream;
 pub use self::transport::Transport;
@@ -174,6 +208,19 @@ pub trait TryWrite: io::Write {
     /// Write a `Buf` into this object, returning how many bytes were written.
     fn try_write_buf<B: Buf>(&mut self, buf: &mut B) -> io::Result<Option<usize>>;
 
+    /// Write bytes from several buffers, in order, into this object,
+    /// returning how many bytes were written.
+    ///
+    /// Sources able to perform vectored writes hand all the buffers to a
+    /// single `writev` call. The default implementation writes the first
+    /// non-empty buffer with `try_write`.
+    fn try_write_vectored(&mut self, bufs: &[&[u8]]) -> io::Result<Option<usize>> {
+        match bufs.iter().find(|buf| !buf.is_empty()) {
+            Some(buf) => self.try_write(buf),
+            None => Ok(Some(0)),
+        }
+    }
+
     /// Try flushing the underlying IO
     fn try_flush(&mut self) -> io::Result<Option<()>>;
 }
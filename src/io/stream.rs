@@ -1,4 +1,5 @@
 use io::{Framed, Parse, Serialize, Readiness};
+use io::codec::{self, Codec, CodecParser, CodecSerializer};
 use bytes::{BlockBuf};
 use std::io;
 
@@ -12,6 +13,15 @@ pub trait Stream: io::Read + io::Write + Readiness {
     {
         Framed::new(self, parse, serialize, BlockBuf::default(), BlockBuf::default())
     }
+
+    /// Frame this stream using a `Codec` for both directions
+    fn framed<C>(self, codec: C) -> Framed<Self, CodecParser<C>, CodecSerializer<C>>
+        where Self: Sized,
+              C: Codec,
+    {
+        let (parse, serialize) = codec::split(codec);
+        self.frame(parse, serialize)
+    }
 }
 
 impl<T: io::Read + io::Write + Readiness> Stream for T {
@@ -11,6 +11,9 @@
 //!
 //! The service trait is decoupled from any notion of a runtime.
 //!
+//! Services can be adapted to one another using the combinators provided by
+//! `ServiceExt`, which live in the `service` module.
+//!
 //! # Reactor
 //!
 //! The Tokio Reactor is a lightweight, event driven, task scheduler. It
@@ -24,6 +27,16 @@
 //!
 //! Tokio aims to provide all the pieces necessary for rapidly developing
 //! protocol implementations. These components exist in the `proto` module.
+//!
+//! # Discovery
+//!
+//! Clients connecting to a set of endpoints that changes at runtime are fed
+//! by the `Discover` trait, found in the `discover` module.
+//!
+//! # Middleware
+//!
+//! Generic `Service` wrappers, such as circuit breaking, exist in the
+//! `middleware` module.
 
 #![deny(warnings, missing_docs)]
 
@@ -37,10 +50,11 @@ extern crate tokio_service;
 #[macro_use]
 extern crate log;
 
+pub mod discover;
 pub mod io;
+pub mod middleware;
 pub mod proto;
 pub mod server;
+pub mod service;
 
-mod service;
-
-pub use self::service::{Service, NewService, SimpleService, simple_service};
+pub use self::service::{Service, NewService, AsyncNewService, ServiceExt, BoxService, SimpleService, simple_service};
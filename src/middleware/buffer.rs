@@ -0,0 +1,391 @@
+//! Share a single service instance between many callers.
+//!
+//! A `Buffer` runs the inner service as a task on an event loop. Calls made
+//! through a `Buffer` are sent to that task over a channel and the response
+//! is sent back once the inner service completes it. Handles are cheap to
+//! clone, which makes `Buffer` a convenient way to share one stateful
+//! service, such as an in-memory cache, between all the connections handled
+//! by `pipeline::Server`.
+//!
+//! The service is created by a factory called on the event loop thread, so
+//! the service itself never moves between threads.
+//!
+//! The channel is bounded: once `capacity` requests are waiting to be picked
+//! up by the task, further calls wait for a slot to free up before sending
+//! their request.
+
+use std::{error, fmt, io};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use futures::stream::Stream;
+use futures::task::{self, Task};
+use futures::{self, Future, Poll, BoxFuture, Complete, Oneshot};
+use tokio_core::{Sender, Receiver, LoopHandle};
+
+use Service;
+
+/// Cloneable handle sending requests to a service running as a task.
+pub struct Buffer<S: Service> {
+    tx: Sender<Request<S>>,
+    shared: Arc<Shared>,
+}
+
+/// Errors returned by a `Buffer` on its own behalf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferError {
+    /// The task running the inner service has shut down.
+    Closed,
+}
+
+type Request<S> = (<S as Service>::Req,
+                   Complete<Result<<S as Service>::Resp, <S as Service>::Error>>);
+
+type Response<S> = Oneshot<Result<<S as Service>::Resp, <S as Service>::Error>>;
+
+// State shared by the handles and the task
+struct Shared {
+    capacity: usize,
+    // Number of requests sent but not yet picked up by the task
+    queued: AtomicUsize,
+    // Calls waiting for a slot
+    parked: Mutex<VecDeque<Task>>,
+    // Set once the task has shut down
+    closed: AtomicBool,
+}
+
+// Future returned by `Buffer::call`
+struct Call<S: Service> {
+    shared: Arc<Shared>,
+    state: State<S>,
+}
+
+enum State<S: Service> {
+    // Waiting for a slot in the buffer
+    Waiting(Sender<Request<S>>, Option<S::Req>),
+    // Waiting for the response
+    Sent(Response<S>),
+}
+
+// Task driving the inner service
+struct Worker<S: Service> {
+    service: S,
+    // `None` once all `Buffer` handles have been dropped
+    requests: Option<Receiver<Request<S>>>,
+    in_flight: Vec<(S::Fut, Complete<Result<S::Resp, S::Error>>)>,
+    shared: Arc<Shared>,
+}
+
+/*
+ *
+ * ===== impl Buffer =====
+ *
+ */
+
+impl<S> Buffer<S>
+    where S: Service,
+          S::Error: From<BufferError>,
+{
+    /// Spawn the service created by `new_service` on the event loop
+    /// referenced by `handle` and return a `Buffer` sending requests to it.
+    ///
+    /// `new_service` is called on the event loop thread. At most `capacity`
+    /// requests may be waiting on the task at any time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn new<F>(handle: LoopHandle, new_service: F, capacity: usize) -> Buffer<S>
+        where F: FnOnce() -> S + Send + 'static,
+    {
+        assert!(capacity > 0, "buffer capacity must be greater than zero");
+
+        let (tx, rx) = handle.clone().channel();
+
+        let shared = Arc::new(Shared {
+            capacity: capacity,
+            queued: AtomicUsize::new(0),
+            parked: Mutex::new(VecDeque::new()),
+            closed: AtomicBool::new(false),
+        });
+
+        let worker_shared = shared.clone();
+
+        handle.add_loop_data(move |_| {
+            rx.and_then(move |rx| {
+                Ok(Worker {
+                    service: new_service(),
+                    requests: Some(rx),
+                    in_flight: vec![],
+                    shared: worker_shared,
+                })
+            }).flatten()
+        }).flatten().forget();
+
+        Buffer {
+            tx: tx,
+            shared: shared,
+        }
+    }
+
+    /// Returns the number of requests waiting to be picked up by the task.
+    pub fn len(&self) -> usize {
+        self.shared.queued.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if no request is waiting to be picked up by the task.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<S> Service for Buffer<S>
+    where S: Service,
+          S::Error: From<BufferError>,
+{
+    type Req = S::Req;
+    type Resp = S::Resp;
+    type Error = S::Error;
+    type Fut = BoxFuture<S::Resp, S::Error>;
+
+    fn call(&self, req: S::Req) -> Self::Fut {
+        let mut call = Call {
+            shared: self.shared.clone(),
+            state: State::Waiting(self.tx.clone(), Some(req)),
+        };
+
+        // Send the request right away if there is room in the buffer
+        if let Err(e) = call.try_send() {
+            return futures::failed(e.into()).boxed();
+        }
+
+        call.boxed()
+    }
+}
+
+impl<S: Service> Clone for Buffer<S> {
+    fn clone(&self) -> Buffer<S> {
+        Buffer {
+            tx: self.tx.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl Shared =====
+ *
+ */
+
+impl Shared {
+    // Reserve a slot in the buffer, returns false if it is full
+    fn acquire(&self) -> bool {
+        if self.queued.fetch_add(1, Ordering::AcqRel) < self.capacity {
+            return true;
+        }
+
+        self.queued.fetch_sub(1, Ordering::AcqRel);
+        false
+    }
+
+    // Release a slot, waking up the waiting calls. All of them are woken up
+    // since some may have been dropped since they parked.
+    fn release(&self) {
+        self.queued.fetch_sub(1, Ordering::AcqRel);
+        self.unpark_all();
+    }
+
+    fn unpark_all(&self) {
+        let parked: Vec<Task> = self.parked.lock().unwrap().drain(..).collect();
+
+        for task in parked {
+            task.unpark();
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl Call =====
+ *
+ */
+
+impl<S: Service> Call<S> {
+    // Send the request if a slot is available. Returns `Ok` if the request
+    // was sent or the buffer is full.
+    fn try_send(&mut self) -> Result<(), BufferError> {
+        if self.shared.closed.load(Ordering::Acquire) {
+            return Err(BufferError::Closed);
+        }
+
+        if !self.shared.acquire() {
+            return Ok(());
+        }
+
+        let rx = match self.state {
+            State::Waiting(ref tx, ref mut req) => {
+                let (complete, rx) = futures::oneshot();
+                let req = req.take().expect("request already sent");
+
+                if tx.send((req, complete)).is_err() {
+                    self.shared.queued.fetch_sub(1, Ordering::AcqRel);
+                    return Err(BufferError::Closed);
+                }
+
+                rx
+            }
+            State::Sent(..) => return Ok(()),
+        };
+
+        self.state = State::Sent(rx);
+        Ok(())
+    }
+}
+
+impl<S> Future for Call<S>
+    where S: Service,
+          S::Error: From<BufferError>,
+{
+    type Item = S::Resp;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<S::Resp, S::Error> {
+        if let State::Waiting(..) = self.state {
+            if let Err(e) = self.try_send() {
+                return Poll::Err(e.into());
+            }
+
+            if let State::Waiting(..) = self.state {
+                // Park before checking again, so that a slot released in
+                // the meantime is not missed.
+                self.shared.parked.lock().unwrap().push_back(task::park());
+
+                if let Err(e) = self.try_send() {
+                    return Poll::Err(e.into());
+                }
+
+                if let State::Waiting(..) = self.state {
+                    trace!("buffer full; waiting for a slot");
+                    return Poll::NotReady;
+                }
+            }
+        }
+
+        match self.state {
+            State::Sent(ref mut rx) => {
+                match rx.poll() {
+                    Poll::Ok(Ok(resp)) => Poll::Ok(resp),
+                    Poll::Ok(Err(e)) => Poll::Err(e),
+                    Poll::Err(_) => Poll::Err(BufferError::Closed.into()),
+                    Poll::NotReady => Poll::NotReady,
+                }
+            }
+            State::Waiting(..) => unreachable!(),
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl Worker =====
+ *
+ */
+
+impl<S: Service> Worker<S> {
+    fn poll_requests(&mut self) -> io::Result<()> {
+        loop {
+            let res = match self.requests {
+                Some(ref mut requests) => requests.poll(),
+                None => return Ok(()),
+            };
+
+            match res {
+                Poll::Ok(Some((req, complete))) => {
+                    self.shared.release();
+
+                    let fut = self.service.call(req);
+                    self.in_flight.push((fut, complete));
+                }
+                Poll::Ok(None) => {
+                    trace!("all buffer handles dropped");
+                    self.requests = None;
+                }
+                Poll::Err(e) => return Err(e),
+                Poll::NotReady => return Ok(()),
+            }
+        }
+    }
+
+    fn poll_in_flight(&mut self) {
+        let mut i = 0;
+
+        while i < self.in_flight.len() {
+            let res = match self.in_flight[i].0.poll() {
+                Poll::Ok(v) => Ok(v),
+                Poll::Err(e) => Err(e),
+                Poll::NotReady => {
+                    i += 1;
+                    continue;
+                }
+            };
+
+            let (_, complete) = self.in_flight.swap_remove(i);
+            complete.complete(res);
+        }
+    }
+}
+
+impl<S: Service> Future for Worker<S> {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        if let Err(e) = self.poll_requests() {
+            return Poll::Err(e);
+        }
+
+        self.poll_in_flight();
+
+        if self.requests.is_none() && self.in_flight.is_empty() {
+            return Poll::Ok(());
+        }
+
+        Poll::NotReady
+    }
+}
+
+impl<S: Service> Drop for Worker<S> {
+    fn drop(&mut self) {
+        // Calls waiting for a slot would otherwise wait forever
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.unpark_all();
+    }
+}
+
+/*
+ *
+ * ===== impl BufferError =====
+ *
+ */
+
+impl fmt::Display for BufferError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", error::Error::description(self))
+    }
+}
+
+impl error::Error for BufferError {
+    fn description(&self) -> &str {
+        match *self {
+            BufferError::Closed => "buffer closed",
+        }
+    }
+}
+
+impl From<BufferError> for io::Error {
+    fn from(err: BufferError) -> io::Error {
+        io::Error::new(io::ErrorKind::BrokenPipe, err)
+    }
+}
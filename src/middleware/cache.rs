@@ -0,0 +1,417 @@
+//! Serve repeated requests from an in-memory cache.
+//!
+//! A `Cache` computes a key for each request using a user provided function.
+//! Successful responses are stored in a size bounded LRU cache and returned
+//! to subsequent requests with the same key without calling the inner
+//! service, until the entry's time to live elapses. Expiration is checked
+//! when an entry is looked up, and a single task on the event loop
+//! periodically sweeps expired entries that are no longer requested.
+//!
+//! Errors may optionally be cached as well (negative caching). A negative
+//! entry only records that the key failed, not the error itself: calls
+//! answered from it receive `CacheError::Negative`.
+
+use std::{error, fmt, io};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use futures::{self, Future, Poll, BoxFuture};
+use tokio_core::LoopHandle;
+
+use Service;
+
+/// Caches the responses of the inner service.
+///
+/// All successful responses share the time to live set by `Builder::ttl`,
+/// and all errors the one set by `Builder::negative_ttl`; there is no per
+/// entry expiration. Expired entries are never returned, but may occupy the
+/// cache until the next periodic sweep, which runs every `min(ttl,
+/// negative_ttl)`.
+///
+/// Cloning a `Cache` clones the inner service, but the clones share the
+/// cached entries.
+pub struct Cache<S: Service, F, K> {
+    inner: S,
+    key: Arc<F>,
+    config: Builder,
+    lru: Arc<Mutex<Lru<K, S::Resp>>>,
+}
+
+/// Configures and creates a `Cache`.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    capacity: usize,
+    ttl: Duration,
+    negative_ttl: Option<Duration>,
+}
+
+/// Errors returned by a `Cache` on its own behalf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheError {
+    /// The call was answered from a cached error.
+    Negative,
+}
+
+struct Lru<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    // Keys ordered by last access
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    capacity: usize,
+}
+
+// Task periodically removing expired entries
+struct Sweep<K, V> {
+    lru: Weak<Mutex<Lru<K, V>>>,
+    interval: Duration,
+    handle: LoopHandle,
+    timer: Option<BoxFuture<(), io::Error>>,
+}
+
+struct Entry<V> {
+    // `None` for negative entries
+    value: Option<V>,
+    expires: Instant,
+    tick: u64,
+}
+
+/*
+ *
+ * ===== impl Builder =====
+ *
+ */
+
+impl Builder {
+    /// Returns a new `Builder` caching at most `capacity` entries.
+    ///
+    /// By default, entries live for 60 seconds and errors are not cached.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn new(capacity: usize) -> Builder {
+        assert!(capacity > 0, "cache capacity must be greater than zero");
+
+        Builder {
+            capacity: capacity,
+            ttl: Duration::from_secs(60),
+            negative_ttl: None,
+        }
+    }
+
+    /// Set how long successful responses are cached.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is zero.
+    pub fn ttl(mut self, val: Duration) -> Builder {
+        assert!(val > Duration::from_secs(0), "ttl must be greater than zero");
+        self.ttl = val;
+        self
+    }
+
+    /// Cache errors for the given duration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is zero.
+    pub fn negative_ttl(mut self, val: Duration) -> Builder {
+        assert!(val > Duration::from_secs(0), "ttl must be greater than zero");
+        self.negative_ttl = Some(val);
+        self
+    }
+
+    /// Create the `Cache` wrapping the given service, keying requests with
+    /// `key`. Expired entries are swept by a task running on the event loop
+    /// referenced by `handle`.
+    pub fn build<S, F, K>(self, handle: LoopHandle, inner: S, key: F) -> Cache<S, F, K>
+        where S: Service,
+              S::Resp: Clone,
+              S::Error: From<CacheError>,
+              F: Fn(&S::Req) -> K + Send + Sync + 'static,
+              K: Hash + Eq + Clone + Send + 'static,
+    {
+        let lru = Arc::new(Mutex::new(Lru::new(self.capacity)));
+
+        let interval = match self.negative_ttl {
+            Some(negative_ttl) if negative_ttl < self.ttl => negative_ttl,
+            _ => self.ttl,
+        };
+
+        let sweep = Sweep {
+            lru: Arc::downgrade(&lru),
+            interval: interval,
+            handle: handle.clone(),
+            timer: None,
+        };
+
+        handle.add_loop_data(|_| sweep).flatten().forget();
+
+        Cache {
+            inner: inner,
+            key: Arc::new(key),
+            config: self,
+            lru: lru,
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl Cache =====
+ *
+ */
+
+impl<S, F, K> Cache<S, F, K>
+    where S: Service,
+          S::Resp: Clone,
+          S::Error: From<CacheError>,
+          F: Fn(&S::Req) -> K + Send + Sync + 'static,
+          K: Hash + Eq + Clone + Send + 'static,
+{
+    /// Returns the number of cached entries.
+    pub fn len(&self) -> usize {
+        self.lru.lock().unwrap().entries.len()
+    }
+
+    /// Returns true if no entry is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all cached entries.
+    pub fn clear(&self) {
+        self.lru.lock().unwrap().clear();
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, F, K> Service for Cache<S, F, K>
+    where S: Service,
+          S::Resp: Clone,
+          S::Error: From<CacheError>,
+          F: Fn(&S::Req) -> K + Send + Sync + 'static,
+          K: Hash + Eq + Clone + Send + 'static,
+{
+    type Req = S::Req;
+    type Resp = S::Resp;
+    type Error = S::Error;
+    type Fut = BoxFuture<S::Resp, S::Error>;
+
+    fn call(&self, req: S::Req) -> Self::Fut {
+        let key = (self.key)(&req);
+
+        match self.lru.lock().unwrap().get(&key, Instant::now()) {
+            Some(Some(v)) => {
+                trace!("cache hit");
+                return futures::finished(v).boxed();
+            }
+            Some(None) => {
+                trace!("negative cache hit");
+                return futures::failed(CacheError::Negative.into()).boxed();
+            }
+            None => {}
+        }
+
+        let lru = self.lru.clone();
+        let ttl = self.config.ttl;
+        let negative_ttl = self.config.negative_ttl;
+
+        self.inner.call(req).then(move |res| {
+            let (value, ttl) = match res {
+                Ok(ref v) => (Some(v.clone()), ttl),
+                Err(_) => {
+                    match negative_ttl {
+                        Some(ttl) => (None, ttl),
+                        None => return res,
+                    }
+                }
+            };
+
+            lru.lock().unwrap().insert(key, value, ttl, Instant::now());
+
+            res
+        }).boxed()
+    }
+}
+
+impl<S, F, K> Clone for Cache<S, F, K>
+    where S: Service + Clone,
+{
+    fn clone(&self) -> Cache<S, F, K> {
+        Cache {
+            inner: self.inner.clone(),
+            key: self.key.clone(),
+            config: self.config.clone(),
+            lru: self.lru.clone(),
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl Lru =====
+ *
+ */
+
+impl<K: Hash + Eq + Clone, V: Clone> Lru<K, V> {
+    fn new(capacity: usize) -> Lru<K, V> {
+        Lru {
+            entries: HashMap::with_capacity(capacity),
+            order: BTreeMap::new(),
+            tick: 0,
+            capacity: capacity,
+        }
+    }
+
+    // Returns `Some(None)` for negative entries
+    fn get(&mut self, key: &K, now: Instant) -> Option<Option<V>> {
+        let tick = self.next_tick();
+
+        let (expired, old) = match self.entries.get_mut(key) {
+            Some(entry) => {
+                if entry.expires <= now {
+                    (true, entry.tick)
+                } else {
+                    let old = entry.tick;
+                    entry.tick = tick;
+                    (false, old)
+                }
+            }
+            None => return None,
+        };
+
+        self.order.remove(&old);
+
+        if expired {
+            self.entries.remove(key);
+            return None;
+        }
+
+        self.order.insert(tick, key.clone());
+        self.entries.get(key).map(|entry| entry.value.clone())
+    }
+
+    fn insert(&mut self, key: K, value: Option<V>, ttl: Duration, now: Instant) {
+        let tick = self.next_tick();
+
+        let entry = Entry {
+            value: value,
+            expires: now + ttl,
+            tick: tick,
+        };
+
+        if let Some(old) = self.entries.insert(key.clone(), entry) {
+            self.order.remove(&old.tick);
+        }
+
+        self.order.insert(tick, key);
+
+        while self.entries.len() > self.capacity {
+            let lru = match self.order.keys().next() {
+                Some(&tick) => tick,
+                None => break,
+            };
+
+            if let Some(key) = self.order.remove(&lru) {
+                trace!("evicting least recently used cache entry");
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    // Remove all expired entries
+    fn sweep(&mut self, now: Instant) {
+        let expired: Vec<(K, u64)> = self.entries.iter()
+            .filter(|&(_, entry)| entry.expires <= now)
+            .map(|(key, entry)| (key.clone(), entry.tick))
+            .collect();
+
+        if !expired.is_empty() {
+            trace!("sweeping expired cache entries; count={}", expired.len());
+        }
+
+        for (key, tick) in expired {
+            self.order.remove(&tick);
+            self.entries.remove(&key);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+/*
+ *
+ * ===== impl Sweep =====
+ *
+ */
+
+impl<K: Hash + Eq + Clone, V: Clone> Future for Sweep<K, V> {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        loop {
+            if self.timer.is_none() {
+                let timer = self.handle.clone().timeout(self.interval).flatten();
+                self.timer = Some(timer.boxed());
+            }
+
+            match self.timer.as_mut().unwrap().poll() {
+                Poll::Ok(()) => {}
+                Poll::Err(e) => return Poll::Err(e),
+                Poll::NotReady => return Poll::NotReady,
+            }
+
+            self.timer = None;
+
+            // Stop sweeping once the cache is gone
+            let lru = match self.lru.upgrade() {
+                Some(lru) => lru,
+                None => return Poll::Ok(()),
+            };
+
+            lru.lock().unwrap().sweep(Instant::now());
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl CacheError =====
+ *
+ */
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", error::Error::description(self))
+    }
+}
+
+impl error::Error for CacheError {
+    fn description(&self) -> &str {
+        match *self {
+            CacheError::Negative => "cached error",
+        }
+    }
+}
+
+impl From<CacheError> for io::Error {
+    fn from(err: CacheError) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, err)
+    }
+}
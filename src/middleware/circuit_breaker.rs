@@ -0,0 +1,437 @@
+//! Fail fast when the inner service is unhealthy.
+//!
+//! A `CircuitBreaker` tracks the outcome of calls over a sliding window. While
+//! the error rate stays below the configured threshold, the breaker is
+//! **closed** and calls are passed through to the inner service.
+//!
+//! Once the error rate reaches the threshold, the breaker **opens** and calls
+//! are rejected immediately with a `CircuitOpen` error, without touching the
+//! inner service. After a cooldown period, the breaker becomes **half-open**
+//! and lets a single probe request through. If the probe succeeds, the
+//! breaker closes again, otherwise it re-opens for another cooldown period.
+
+use std::{error, fmt, io};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::{self, Future, BoxFuture};
+
+use Service;
+
+/// Rejects calls to the inner service while it is failing.
+///
+/// Cloning a `CircuitBreaker` clones the inner service, but the clones share
+/// the same circuit state.
+pub struct CircuitBreaker<S> {
+    inner: S,
+    state: Arc<Mutex<Circuit>>,
+}
+
+/// Configures and creates a `CircuitBreaker`.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    window: Duration,
+    buckets: usize,
+    min_requests: u64,
+    error_rate: f64,
+    cooldown: Duration,
+}
+
+/// The state of a circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Calls are passed through to the inner service.
+    Closed,
+    /// Calls are rejected without calling the inner service.
+    Open,
+    /// A single probe call is passed through to the inner service to
+    /// determine if the circuit should close.
+    HalfOpen,
+}
+
+/// Error returned when a call is rejected because the circuit is open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitOpen;
+
+struct Circuit {
+    config: Builder,
+    state: Inner,
+    window: Window,
+}
+
+// Records the outcome of a call admitted by the circuit. A probe dropped
+// before completing is recorded as a failure, so the circuit does not wait
+// for it forever.
+struct Outcome {
+    state: Arc<Mutex<Circuit>>,
+    probe: bool,
+    done: bool,
+}
+
+enum Inner {
+    Closed,
+    Open(Instant),
+    // The probe request is in flight, other calls are rejected
+    HalfOpen,
+}
+
+// Call outcomes over a sliding window, bucketed to bound memory use
+struct Window {
+    buckets: Vec<Bucket>,
+    // Duration covered by each bucket
+    granularity: Duration,
+    // Index of the current bucket
+    pos: usize,
+    // Start of the current bucket
+    start: Instant,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Bucket {
+    successes: u64,
+    failures: u64,
+}
+
+/*
+ *
+ * ===== impl Builder =====
+ *
+ */
+
+impl Builder {
+    /// Returns a new `Builder` with the default configuration.
+    ///
+    /// By default, the breaker opens once half of the calls made over the
+    /// last 10 seconds failed, as long as at least 20 calls were made. The
+    /// cooldown period is 5 seconds.
+    pub fn new() -> Builder {
+        Builder {
+            window: Duration::from_secs(10),
+            buckets: 10,
+            min_requests: 20,
+            error_rate: 0.5,
+            cooldown: Duration::from_secs(5),
+        }
+    }
+
+    /// Set the duration of the sliding window over which the error rate is
+    /// computed.
+    pub fn window(mut self, val: Duration) -> Builder {
+        self.window = val;
+        self
+    }
+
+    /// Set the number of buckets the sliding window is divided into.
+    ///
+    /// More buckets make the window slide more smoothly at the cost of a bit
+    /// more memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is zero.
+    pub fn buckets(mut self, val: usize) -> Builder {
+        assert!(val > 0, "the window requires at least one bucket");
+        self.buckets = val;
+        self
+    }
+
+    /// Set the minimum number of calls in the window before the error rate is
+    /// considered.
+    pub fn min_requests(mut self, val: u64) -> Builder {
+        self.min_requests = val;
+        self
+    }
+
+    /// Set the error rate, between 0.0 and 1.0, at which the breaker opens.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is less than 0.0 or greater than 1.0.
+    pub fn error_rate(mut self, val: f64) -> Builder {
+        assert!(val >= 0.0 && val <= 1.0, "error rate must be between 0.0 and 1.0");
+        self.error_rate = val;
+        self
+    }
+
+    /// Set how long the breaker stays open before probing the inner service.
+    pub fn cooldown(mut self, val: Duration) -> Builder {
+        self.cooldown = val;
+        self
+    }
+
+    /// Create the `CircuitBreaker` wrapping the given service.
+    pub fn build<S: Service>(self, inner: S) -> CircuitBreaker<S> {
+        let window = Window::new(self.window, self.buckets);
+
+        CircuitBreaker {
+            inner: inner,
+            state: Arc::new(Mutex::new(Circuit {
+                config: self,
+                state: Inner::Closed,
+                window: window,
+            })),
+        }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+/*
+ *
+ * ===== impl CircuitBreaker =====
+ *
+ */
+
+impl<S: Service> CircuitBreaker<S> {
+    /// Create a new `CircuitBreaker` with the default configuration.
+    pub fn new(inner: S) -> CircuitBreaker<S> {
+        Builder::new().build(inner)
+    }
+
+    /// Returns the current state of the circuit.
+    pub fn state(&self) -> State {
+        self.state.lock().unwrap().state(Instant::now())
+    }
+
+    /// Returns the error rate over the current window.
+    pub fn error_rate(&self) -> f64 {
+        let mut circuit = self.state.lock().unwrap();
+        circuit.window.advance(Instant::now());
+        circuit.window.error_rate()
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S> Service for CircuitBreaker<S>
+    where S: Service,
+          S::Error: From<CircuitOpen>,
+{
+    type Req = S::Req;
+    type Resp = S::Resp;
+    type Error = S::Error;
+    type Fut = BoxFuture<S::Resp, S::Error>;
+
+    fn call(&self, req: S::Req) -> Self::Fut {
+        let probe = match self.state.lock().unwrap().acquire(Instant::now()) {
+            Some(probe) => probe,
+            None => {
+                trace!("circuit open; rejecting call");
+                return futures::failed(CircuitOpen.into()).boxed();
+            }
+        };
+
+        let outcome = Outcome {
+            state: self.state.clone(),
+            probe: probe,
+            done: false,
+        };
+
+        self.inner.call(req).then(move |res| {
+            outcome.complete(res.is_ok());
+            res
+        }).boxed()
+    }
+}
+
+impl<S: Clone> Clone for CircuitBreaker<S> {
+    fn clone(&self) -> CircuitBreaker<S> {
+        CircuitBreaker {
+            inner: self.inner.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl Circuit =====
+ *
+ */
+
+impl Circuit {
+    fn state(&self, now: Instant) -> State {
+        match self.state {
+            Inner::Closed => State::Closed,
+            Inner::Open(until) if now < until => State::Open,
+            Inner::Open(..) | Inner::HalfOpen => State::HalfOpen,
+        }
+    }
+
+    // Returns `None` if the call is rejected, otherwise whether the call is
+    // the probe of a half-open circuit.
+    fn acquire(&mut self, now: Instant) -> Option<bool> {
+        match self.state {
+            Inner::Closed => Some(false),
+            Inner::Open(until) => {
+                if now < until {
+                    return None;
+                }
+
+                debug!("circuit half-open; sending probe");
+                self.state = Inner::HalfOpen;
+                Some(true)
+            }
+            Inner::HalfOpen => None,
+        }
+    }
+
+    fn record(&mut self, success: bool, probe: bool, now: Instant) {
+        match self.state {
+            Inner::HalfOpen if !probe => {
+                // A call dispatched before the circuit opened completed, only
+                // the probe decides whether the circuit closes.
+            }
+            Inner::HalfOpen => {
+                if success {
+                    debug!("probe succeeded; closing circuit");
+                    self.state = Inner::Closed;
+                    self.window.reset(now);
+                } else {
+                    debug!("probe failed; re-opening circuit");
+                    self.state = Inner::Open(now + self.config.cooldown);
+                }
+            }
+            Inner::Closed => {
+                self.window.advance(now);
+                self.window.record(success);
+
+                if self.window.total() >= self.config.min_requests &&
+                    self.window.error_rate() >= self.config.error_rate
+                {
+                    debug!("error rate exceeded; opening circuit; rate={}",
+                           self.window.error_rate());
+                    self.state = Inner::Open(now + self.config.cooldown);
+                }
+            }
+            Inner::Open(..) => {
+                // A call dispatched before the circuit opened completed, it
+                // has no influence on the open circuit.
+            }
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl Outcome =====
+ *
+ */
+
+impl Outcome {
+    fn complete(mut self, success: bool) {
+        self.done = true;
+        self.state.lock().unwrap().record(success, self.probe, Instant::now());
+    }
+}
+
+impl Drop for Outcome {
+    fn drop(&mut self) {
+        if !self.done && self.probe {
+            debug!("probe dropped before completing");
+            self.state.lock().unwrap().record(false, true, Instant::now());
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl Window =====
+ *
+ */
+
+impl Window {
+    fn new(window: Duration, buckets: usize) -> Window {
+        let granularity = window / buckets as u32;
+
+        Window {
+            buckets: vec![Bucket::default(); buckets],
+            granularity: granularity,
+            pos: 0,
+            start: Instant::now(),
+        }
+    }
+
+    // Rotate out buckets that have fallen out of the window
+    fn advance(&mut self, now: Instant) {
+        let len = self.buckets.len();
+        let mut rotated = 0;
+
+        while now.duration_since(self.start) >= self.granularity {
+            if rotated == len {
+                // The whole window expired, skip ahead
+                self.start = now;
+                break;
+            }
+
+            self.pos = (self.pos + 1) % len;
+            self.buckets[self.pos] = Bucket::default();
+            self.start += self.granularity;
+            rotated += 1;
+        }
+    }
+
+    fn reset(&mut self, now: Instant) {
+        for bucket in &mut self.buckets {
+            *bucket = Bucket::default();
+        }
+
+        self.start = now;
+    }
+
+    fn record(&mut self, success: bool) {
+        let bucket = &mut self.buckets[self.pos];
+
+        if success {
+            bucket.successes += 1;
+        } else {
+            bucket.failures += 1;
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.buckets.iter().map(|b| b.successes + b.failures).sum()
+    }
+
+    fn error_rate(&self) -> f64 {
+        let total = self.total();
+
+        if total == 0 {
+            return 0.0;
+        }
+
+        let failures: u64 = self.buckets.iter().map(|b| b.failures).sum();
+        failures as f64 / total as f64
+    }
+}
+
+/*
+ *
+ * ===== impl CircuitOpen =====
+ *
+ */
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "circuit breaker open")
+    }
+}
+
+impl error::Error for CircuitOpen {
+    fn description(&self) -> &str {
+        "circuit breaker open"
+    }
+}
+
+impl From<CircuitOpen> for io::Error {
+    fn from(err: CircuitOpen) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, err)
+    }
+}
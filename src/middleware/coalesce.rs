@@ -0,0 +1,239 @@
+//! Share one in-flight call between concurrent identical requests.
+//!
+//! A `Coalesce` service computes a key for each request using a user provided
+//! function. When a call is made while another call with the same key is
+//! still in flight, the inner service is not called again. Instead, the new
+//! call waits on the in-flight one and receives a clone of its response.
+//!
+//! Errors cannot in general be cloned, so only the call that was actually
+//! dispatched to the inner service receives the original error. The calls
+//! waiting on it receive `CoalesceError::Failed` instead.
+
+use std::{error, fmt, io};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use futures::{Future, Poll, Oneshot, Complete};
+
+use Service;
+
+/// Coalesces concurrent calls with the same key into a single call.
+///
+/// Cloning a `Coalesce` clones the inner service, but the clones share the
+/// set of in-flight calls.
+pub struct Coalesce<S: Service, F, K> {
+    inner: S,
+    key: Arc<F>,
+    in_flight: Arc<Mutex<InFlight<S::Resp, K>>>,
+}
+
+/// Future returned by `Coalesce`.
+pub struct CoalesceFuture<S: Service, K>
+    where K: Hash + Eq,
+{
+    state: State<S, K>,
+}
+
+/// Errors returned to calls waiting on another call with the same key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoalesceError {
+    /// The call that was waited on failed.
+    Failed,
+    /// The call that was waited on was canceled before completing.
+    Canceled,
+}
+
+type InFlight<T, K> = HashMap<K, Vec<Complete<Result<T, CoalesceError>>>>;
+
+enum State<S: Service, K>
+    where K: Hash + Eq,
+{
+    // This call was dispatched to the inner service
+    Leader {
+        fut: S::Fut,
+        key: Option<K>,
+        in_flight: Arc<Mutex<InFlight<S::Resp, K>>>,
+    },
+    // This call waits on the leader
+    Follower(Oneshot<Result<S::Resp, CoalesceError>>),
+}
+
+/*
+ *
+ * ===== impl Coalesce =====
+ *
+ */
+
+impl<S, F, K> Coalesce<S, F, K>
+    where S: Service,
+          S::Resp: Clone,
+          S::Error: From<CoalesceError>,
+          F: Fn(&S::Req) -> K + Send + Sync + 'static,
+          K: Hash + Eq + Clone + Send + 'static,
+{
+    /// Create a new `Coalesce` service keying requests with `key`.
+    pub fn new(inner: S, key: F) -> Coalesce<S, F, K> {
+        Coalesce {
+            inner: inner,
+            key: Arc::new(key),
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the number of distinct keys currently in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, F, K> Service for Coalesce<S, F, K>
+    where S: Service,
+          S::Resp: Clone,
+          S::Error: From<CoalesceError>,
+          F: Fn(&S::Req) -> K + Send + Sync + 'static,
+          K: Hash + Eq + Clone + Send + 'static,
+{
+    type Req = S::Req;
+    type Resp = S::Resp;
+    type Error = S::Error;
+    type Fut = CoalesceFuture<S, K>;
+
+    fn call(&self, req: S::Req) -> Self::Fut {
+        let key = (self.key)(&req);
+        let mut in_flight = self.in_flight.lock().unwrap();
+
+        if let Some(waiters) = in_flight.get_mut(&key) {
+            trace!("coalescing call with in-flight call");
+
+            let (tx, rx) = ::futures::oneshot();
+            waiters.push(tx);
+
+            return CoalesceFuture { state: State::Follower(rx) };
+        }
+
+        in_flight.insert(key.clone(), vec![]);
+        drop(in_flight);
+
+        CoalesceFuture {
+            state: State::Leader {
+                fut: self.inner.call(req),
+                key: Some(key),
+                in_flight: self.in_flight.clone(),
+            },
+        }
+    }
+}
+
+impl<S, F, K> Clone for Coalesce<S, F, K>
+    where S: Service + Clone,
+{
+    fn clone(&self) -> Coalesce<S, F, K> {
+        Coalesce {
+            inner: self.inner.clone(),
+            key: self.key.clone(),
+            in_flight: self.in_flight.clone(),
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl CoalesceFuture =====
+ *
+ */
+
+impl<S, K> Future for CoalesceFuture<S, K>
+    where S: Service,
+          S::Resp: Clone,
+          S::Error: From<CoalesceError>,
+          K: Hash + Eq,
+{
+    type Item = S::Resp;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<S::Resp, S::Error> {
+        match self.state {
+            State::Leader { ref mut fut, ref mut key, ref in_flight } => {
+                let res = match fut.poll() {
+                    Poll::Ok(v) => Ok(v),
+                    Poll::Err(e) => Err(e),
+                    Poll::NotReady => return Poll::NotReady,
+                };
+
+                let waiters = key.take()
+                    .and_then(|key| in_flight.lock().unwrap().remove(&key))
+                    .unwrap_or(vec![]);
+
+                trace!("completing coalesced call; waiters={}", waiters.len());
+
+                for waiter in waiters {
+                    waiter.complete(match res {
+                        Ok(ref v) => Ok(v.clone()),
+                        Err(_) => Err(CoalesceError::Failed),
+                    });
+                }
+
+                match res {
+                    Ok(v) => Poll::Ok(v),
+                    Err(e) => Poll::Err(e),
+                }
+            }
+            State::Follower(ref mut rx) => {
+                match rx.poll() {
+                    Poll::Ok(Ok(v)) => Poll::Ok(v),
+                    Poll::Ok(Err(e)) => Poll::Err(e.into()),
+                    Poll::Err(_) => Poll::Err(CoalesceError::Canceled.into()),
+                    Poll::NotReady => Poll::NotReady,
+                }
+            }
+        }
+    }
+}
+
+impl<S, K> Drop for CoalesceFuture<S, K>
+    where S: Service,
+          K: Hash + Eq,
+{
+    fn drop(&mut self) {
+        if let State::Leader { ref mut key, ref in_flight, .. } = self.state {
+            // The leader was dropped before completing, dropping the waiters
+            // cancels them.
+            if let Some(key) = key.take() {
+                in_flight.lock().unwrap().remove(&key);
+            }
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl CoalesceError =====
+ *
+ */
+
+impl fmt::Display for CoalesceError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", error::Error::description(self))
+    }
+}
+
+impl error::Error for CoalesceError {
+    fn description(&self) -> &str {
+        match *self {
+            CoalesceError::Failed => "coalesced call failed",
+            CoalesceError::Canceled => "coalesced call canceled",
+        }
+    }
+}
+
+impl From<CoalesceError> for io::Error {
+    fn from(err: CoalesceError) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, err)
+    }
+}
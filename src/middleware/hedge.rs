@@ -0,0 +1,323 @@
+//! Reduce tail latency by hedging slow requests.
+//!
+//! A `Hedge` service keeps a histogram of the latency of the calls it makes,
+//! successful or not. Only the first copy of each request is measured.
+//! When a call has not completed after the configured latency percentile, a
+//! second copy of the request is sent and whichever response arrives first is
+//! used. The other response future is dropped.
+//!
+//! The inner service is expected to route the second copy to a different
+//! endpoint than the first one, as a `pipeline::Pool` does by picking the
+//! least loaded connection. Since requests may be sent twice, only idempotent
+//! requests should be hedged.
+//!
+//! The extra load is bounded by a budget: at most the configured fraction of
+//! requests are hedged.
+
+use std::cmp;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::{Future, Poll, BoxFuture};
+use tokio_core::LoopHandle;
+
+use Service;
+use super::metrics::Histogram;
+
+/// Sends a second copy of slow requests, using the first response.
+pub struct Hedge<S> {
+    inner: S,
+    handle: LoopHandle,
+    shared: Arc<Shared>,
+}
+
+/// Configures and creates a `Hedge`.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    percentile: f64,
+    min_samples: usize,
+    budget: f64,
+}
+
+/// Future returned by `Hedge`.
+pub struct HedgeFuture<S: Service> {
+    // The service, kept to send the second copy of the request
+    service: Option<S>,
+    request: Option<S::Req>,
+    primary: Option<S::Fut>,
+    secondary: Option<S::Fut>,
+    // Fires once the hedge delay elapsed
+    timer: Option<BoxFuture<(), ::std::io::Error>>,
+    start: Instant,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    config: Builder,
+    latency: Histogram,
+    requests: AtomicUsize,
+    hedged: AtomicUsize,
+}
+
+/*
+ *
+ * ===== impl Builder =====
+ *
+ */
+
+impl Builder {
+    /// Returns a new `Builder` with the default configuration.
+    ///
+    /// By default, requests that have not completed after the 95th latency
+    /// percentile are hedged, as long as at least 100 latency samples were
+    /// recorded. At most 10% of requests are hedged.
+    pub fn new() -> Builder {
+        Builder {
+            percentile: 0.95,
+            min_samples: 100,
+            budget: 0.1,
+        }
+    }
+
+    /// Set the latency percentile, between 0.0 and 1.0, after which a
+    /// request is hedged.
+    pub fn percentile(mut self, val: f64) -> Builder {
+        assert!(val >= 0.0 && val <= 1.0, "percentile must be between 0.0 and 1.0");
+        self.percentile = val;
+        self
+    }
+
+    /// Set the number of latency samples required before any request is
+    /// hedged.
+    pub fn min_samples(mut self, val: usize) -> Builder {
+        self.min_samples = val;
+        self
+    }
+
+    /// Set the maximum fraction, between 0.0 and 1.0, of requests that may
+    /// be hedged.
+    pub fn budget(mut self, val: f64) -> Builder {
+        assert!(val >= 0.0 && val <= 1.0, "budget must be between 0.0 and 1.0");
+        self.budget = val;
+        self
+    }
+
+    /// Create the `Hedge` wrapping the given service. The hedge timers run on
+    /// the event loop referenced by `handle`.
+    pub fn build<S>(self, handle: LoopHandle, inner: S) -> Hedge<S>
+        where S: Service + Clone,
+              S::Req: Clone,
+    {
+        Hedge {
+            inner: inner,
+            handle: handle,
+            shared: Arc::new(Shared {
+                config: self,
+                latency: Histogram::new(),
+                requests: AtomicUsize::new(0),
+                hedged: AtomicUsize::new(0),
+            }),
+        }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+/*
+ *
+ * ===== impl Hedge =====
+ *
+ */
+
+impl<S> Hedge<S>
+    where S: Service + Clone,
+          S::Req: Clone,
+{
+    /// Create a new `Hedge` with the default configuration.
+    pub fn new(handle: LoopHandle, inner: S) -> Hedge<S> {
+        Builder::new().build(handle, inner)
+    }
+
+    /// Returns the latency histogram used to compute the hedge delay.
+    pub fn latency(&self) -> &Histogram {
+        &self.shared.latency
+    }
+
+    /// Returns the number of requests that were hedged.
+    pub fn hedged(&self) -> usize {
+        self.shared.hedged.load(Ordering::Relaxed)
+    }
+}
+
+impl<S> Service for Hedge<S>
+    where S: Service + Clone,
+          S::Req: Clone,
+{
+    type Req = S::Req;
+    type Resp = S::Resp;
+    type Error = S::Error;
+    type Fut = HedgeFuture<S>;
+
+    fn call(&self, req: S::Req) -> Self::Fut {
+        self.shared.requests.fetch_add(1, Ordering::Relaxed);
+
+        let timer = self.shared.delay().map(|delay| {
+            trace!("hedging after {:?}", delay);
+            self.handle.clone().timeout(delay).flatten().boxed()
+        });
+
+        let (service, request) = if timer.is_some() {
+            (Some(self.inner.clone()), Some(req.clone()))
+        } else {
+            (None, None)
+        };
+
+        HedgeFuture {
+            service: service,
+            request: request,
+            primary: Some(self.inner.call(req)),
+            secondary: None,
+            timer: timer,
+            start: Instant::now(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<S: Clone> Clone for Hedge<S> {
+    fn clone(&self) -> Hedge<S> {
+        Hedge {
+            inner: self.inner.clone(),
+            handle: self.handle.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl HedgeFuture =====
+ *
+ */
+
+impl<S: Service> HedgeFuture<S> {
+    // Poll a call, returning `Some` once the final result is known
+    fn poll_call(&mut self, secondary: bool) -> Option<Poll<S::Resp, S::Error>> {
+        let res = {
+            let slot = if secondary { &mut self.secondary } else { &mut self.primary };
+
+            let res = match *slot {
+                Some(ref mut fut) => fut.poll(),
+                None => return None,
+            };
+
+            match res {
+                Poll::NotReady => return None,
+                res => {
+                    *slot = None;
+                    res
+                }
+            }
+        };
+
+        // Only the primary call measures the latency of the inner service,
+        // the hedge started late and its latency is not comparable.
+        if !secondary {
+            self.shared.latency.record(self.start.elapsed());
+        }
+
+        match res {
+            Poll::Ok(v) => Some(Poll::Ok(v)),
+            Poll::Err(e) => {
+                let other = if secondary { &self.primary } else { &self.secondary };
+
+                if other.is_some() {
+                    // Wait on the other call
+                    return None;
+                }
+
+                Some(Poll::Err(e))
+            }
+            Poll::NotReady => unreachable!(),
+        }
+    }
+
+    fn poll_timer(&mut self) {
+        let fired = match self.timer {
+            Some(ref mut timer) => {
+                match timer.poll() {
+                    Poll::Ok(()) => true,
+                    // A failing timer only means the request is not hedged
+                    Poll::Err(_) => false,
+                    Poll::NotReady => return,
+                }
+            }
+            None => return,
+        };
+
+        self.timer = None;
+
+        if !fired || self.primary.is_none() || !self.shared.acquire() {
+            return;
+        }
+
+        if let (Some(service), Some(request)) = (self.service.take(), self.request.take()) {
+            debug!("hedging request");
+            self.secondary = Some(service.call(request));
+        }
+    }
+}
+
+impl<S: Service> Future for HedgeFuture<S> {
+    type Item = S::Resp;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<S::Resp, S::Error> {
+        if let Some(res) = self.poll_call(false) {
+            return res;
+        }
+
+        self.poll_timer();
+
+        if let Some(res) = self.poll_call(true) {
+            return res;
+        }
+
+        Poll::NotReady
+    }
+}
+
+/*
+ *
+ * ===== impl Shared =====
+ *
+ */
+
+impl Shared {
+    // Returns the delay after which a request should be hedged, if any
+    fn delay(&self) -> Option<Duration> {
+        if self.config.budget == 0.0 || self.latency.count() < self.config.min_samples {
+            return None;
+        }
+
+        self.latency.percentile(self.config.percentile)
+    }
+
+    // Returns true if the budget allows hedging one more request
+    fn acquire(&self) -> bool {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let allowed = cmp::max(1, (requests as f64 * self.config.budget) as usize);
+
+        if self.hedged.fetch_add(1, Ordering::Relaxed) < allowed {
+            return true;
+        }
+
+        self.hedged.fetch_sub(1, Ordering::Relaxed);
+        false
+    }
+}
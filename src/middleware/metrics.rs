@@ -0,0 +1,323 @@
+//! Record latency and outcome metrics for calls to a service.
+//!
+//! A `Metrics` service reports the start and completion of every call to a
+//! `Sink`. The default sink, `Stats`, keeps success and error counters, a
+//! gauge of the calls currently in flight and a latency `Histogram`, all of
+//! which may be read at any time. Implement `Sink` to export the metrics to
+//! another system instead.
+//!
+//! `Metrics` works with any `Service`, so it may wrap both a service handed
+//! to `pipeline::Server` and a `pipeline::Client`.
+
+use std::cmp;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::{Future, Poll};
+
+use Service;
+
+/// Records metrics for each call to the inner service.
+///
+/// Cloning a `Metrics` clones the inner service, but the clones report to the
+/// same sink.
+pub struct Metrics<S, K = Stats> {
+    inner: S,
+    sink: Arc<K>,
+}
+
+/// Future returned by `Metrics`.
+pub struct MetricsFuture<F, K: Sink> {
+    fut: F,
+    sink: Arc<K>,
+    start: Instant,
+    done: bool,
+}
+
+/// Receives call events from a `Metrics` service.
+pub trait Sink: Send + Sync + 'static {
+    /// Called when a call to the service is made.
+    fn started(&self) {
+    }
+
+    /// Called when a call completes, with the time elapsed since the call was
+    /// made.
+    fn finished(&self, latency: Duration, outcome: Outcome);
+}
+
+/// The outcome of a call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The call completed successfully.
+    Success,
+    /// The call completed with an error.
+    Error,
+    /// The response future was dropped before the call completed.
+    Canceled,
+}
+
+/// In-memory `Sink` tracking counters, an in-flight gauge and a latency
+/// histogram.
+#[derive(Debug, Default)]
+pub struct Stats {
+    successes: AtomicUsize,
+    errors: AtomicUsize,
+    canceled: AtomicUsize,
+    in_flight: AtomicUsize,
+    latency: Histogram,
+}
+
+/// A lock-free latency histogram with exponentially sized buckets.
+///
+/// Bucket `i` counts latencies between `2^i` and `2^(i+1)` microseconds, so
+/// reported percentiles are accurate to within a factor of two.
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: Vec<AtomicUsize>,
+}
+
+const BUCKETS: usize = 40;
+
+/*
+ *
+ * ===== impl Metrics =====
+ *
+ */
+
+impl<S: Service> Metrics<S, Stats> {
+    /// Create a new `Metrics` service recording to a new `Stats` sink.
+    pub fn new(inner: S) -> Metrics<S, Stats> {
+        Metrics::with_sink(inner, Stats::new())
+    }
+}
+
+impl<S: Service, K: Sink> Metrics<S, K> {
+    /// Create a new `Metrics` service recording to the given sink.
+    pub fn with_sink(inner: S, sink: K) -> Metrics<S, K> {
+        Metrics {
+            inner: inner,
+            sink: Arc::new(sink),
+        }
+    }
+
+    /// Returns a reference to the sink.
+    pub fn sink(&self) -> &K {
+        &self.sink
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: Service, K: Sink> Service for Metrics<S, K> {
+    type Req = S::Req;
+    type Resp = S::Resp;
+    type Error = S::Error;
+    type Fut = MetricsFuture<S::Fut, K>;
+
+    fn call(&self, req: S::Req) -> Self::Fut {
+        self.sink.started();
+
+        MetricsFuture {
+            fut: self.inner.call(req),
+            sink: self.sink.clone(),
+            start: Instant::now(),
+            done: false,
+        }
+    }
+}
+
+impl<S: Clone, K> Clone for Metrics<S, K> {
+    fn clone(&self) -> Metrics<S, K> {
+        Metrics {
+            inner: self.inner.clone(),
+            sink: self.sink.clone(),
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl MetricsFuture =====
+ *
+ */
+
+impl<F: Future, K: Sink> Future for MetricsFuture<F, K> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let (res, outcome) = match self.fut.poll() {
+            Poll::Ok(v) => (Poll::Ok(v), Outcome::Success),
+            Poll::Err(e) => (Poll::Err(e), Outcome::Error),
+            Poll::NotReady => return Poll::NotReady,
+        };
+
+        self.done = true;
+        self.sink.finished(self.start.elapsed(), outcome);
+
+        res
+    }
+}
+
+impl<F, K: Sink> Drop for MetricsFuture<F, K> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.sink.finished(self.start.elapsed(), Outcome::Canceled);
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl Stats =====
+ *
+ */
+
+impl Stats {
+    /// Returns a new `Stats` with all counters set to zero.
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+
+    /// Returns the number of calls that completed successfully.
+    pub fn successes(&self) -> usize {
+        self.successes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of calls that completed with an error.
+    pub fn errors(&self) -> usize {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of calls whose response future was dropped before
+    /// completing.
+    pub fn canceled(&self) -> usize {
+        self.canceled.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of calls currently in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Returns the latency histogram of completed calls.
+    pub fn latency(&self) -> &Histogram {
+        &self.latency
+    }
+}
+
+impl Sink for Stats {
+    fn started(&self) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn finished(&self, latency: Duration, outcome: Outcome) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+
+        let counter = match outcome {
+            Outcome::Success => &self.successes,
+            Outcome::Error => &self.errors,
+            Outcome::Canceled => &self.canceled,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        if outcome != Outcome::Canceled {
+            self.latency.record(latency);
+        }
+    }
+}
+
+impl<K: Sink> Sink for Arc<K> {
+    fn started(&self) {
+        (**self).started()
+    }
+
+    fn finished(&self, latency: Duration, outcome: Outcome) {
+        (**self).finished(latency, outcome)
+    }
+}
+
+/*
+ *
+ * ===== impl Histogram =====
+ *
+ */
+
+impl Histogram {
+    /// Returns a new, empty `Histogram`.
+    pub fn new() -> Histogram {
+        Histogram {
+            buckets: (0..BUCKETS).map(|_| AtomicUsize::new(0)).collect(),
+        }
+    }
+
+    /// Record a latency value.
+    pub fn record(&self, latency: Duration) {
+        let micros = latency.as_secs()
+            .saturating_mul(1_000_000)
+            .saturating_add(latency.subsec_nanos() as u64 / 1_000);
+
+        // Index of the most significant bit
+        let bits = 64 - micros.leading_zeros() as usize;
+        let idx = cmp::min(bits.saturating_sub(1), BUCKETS - 1);
+
+        self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of recorded values.
+    pub fn count(&self) -> usize {
+        self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Returns an upper bound of the latency below which the given fraction,
+    /// between 0.0 and 1.0, of the recorded values fall.
+    ///
+    /// Returns `None` if no values have been recorded.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let counts: Vec<usize> = self.buckets.iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+
+        let total: usize = counts.iter().sum();
+
+        if total == 0 {
+            return None;
+        }
+
+        let target = cmp::max(1, (total as f64 * p).ceil() as usize);
+        let mut seen = 0;
+
+        for (i, &count) in counts.iter().enumerate() {
+            seen += count;
+
+            if seen >= target {
+                return Some(micros(1 << (i + 1)));
+            }
+        }
+
+        Some(micros(1 << BUCKETS))
+    }
+
+    /// Reset all buckets to zero.
+    pub fn clear(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new()
+    }
+}
+
+// Returns a `Duration` of `n` microseconds
+fn micros(n: u64) -> Duration {
+    Duration::new(n / 1_000_000, (n % 1_000_000) as u32 * 1_000)
+}
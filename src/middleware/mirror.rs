@@ -0,0 +1,208 @@
+//! Duplicate a sample of live traffic to a shadow service.
+//!
+//! A `Mirror` answers every request from the primary service. A configurable
+//! fraction of the requests is also sent to a shadow service, for example a
+//! new backend being tested under real traffic. Shadow calls are fire and
+//! forget: the primary is called first, and the outcome of the shadow call,
+//! even a panic, never affects the response returned to the caller.
+//!
+//! An optional comparator may be set to check the shadow responses against
+//! the primary ones. Mismatches are logged and counted.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::{self, Future, BoxFuture};
+
+use Service;
+
+/// Sends a sample of requests to a shadow service.
+///
+/// Cloning a `Mirror` clones both services, but the clones share the sampling
+/// state and mismatch counter.
+pub struct Mirror<S: Service, M: Service> {
+    primary: S,
+    shadow: M,
+    compare: Option<Arc<Comparator<S::Resp, M::Resp>>>,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    rate: f64,
+    requests: AtomicUsize,
+    mirrored: AtomicUsize,
+    mismatches: AtomicUsize,
+}
+
+struct Comparator<A, B> {
+    // Captured when `A: Clone` is known, see `Mirror::comparator`
+    snapshot: fn(&A) -> A,
+    matches: Box<Fn(&A, &B) -> bool + Send + Sync>,
+}
+
+/*
+ *
+ * ===== impl Mirror =====
+ *
+ */
+
+impl<S, M> Mirror<S, M>
+    where S: Service,
+          S::Req: Clone,
+          M: Service<Req = S::Req>,
+{
+    /// Create a new `Mirror` sending the given fraction, between 0.0 and
+    /// 1.0, of requests to `shadow` in addition to `primary`.
+    ///
+    /// Requests are sampled deterministically: with a rate of 0.25, every
+    /// fourth request is mirrored.
+    pub fn new(primary: S, shadow: M, rate: f64) -> Mirror<S, M> {
+        assert!(rate >= 0.0 && rate <= 1.0, "sampling rate must be between 0.0 and 1.0");
+
+        Mirror {
+            primary: primary,
+            shadow: shadow,
+            compare: None,
+            shared: Arc::new(Shared {
+                rate: rate,
+                requests: AtomicUsize::new(0),
+                mirrored: AtomicUsize::new(0),
+                mismatches: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Compare each successful shadow response with the corresponding
+    /// successful primary response using `matches`.
+    ///
+    /// When `matches` returns false, the mismatch is logged and counted.
+    pub fn comparator<F>(mut self, matches: F) -> Mirror<S, M>
+        where F: Fn(&S::Resp, &M::Resp) -> bool + Send + Sync + 'static,
+              S::Resp: Clone,
+    {
+        self.compare = Some(Arc::new(Comparator {
+            snapshot: Clone::clone,
+            matches: Box::new(matches),
+        }));
+        self
+    }
+
+    /// Returns the number of requests sent to the shadow service.
+    pub fn mirrored(&self) -> usize {
+        self.shared.mirrored.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of shadow responses that did not match the primary
+    /// response.
+    pub fn mismatches(&self) -> usize {
+        self.shared.mismatches.load(Ordering::Relaxed)
+    }
+
+    /// Returns a reference to the primary service.
+    pub fn get_ref(&self) -> &S {
+        &self.primary
+    }
+}
+
+impl<S, M> Service for Mirror<S, M>
+    where S: Service,
+          S::Req: Clone,
+          M: Service<Req = S::Req>,
+{
+    type Req = S::Req;
+    type Resp = S::Resp;
+    type Error = S::Error;
+    type Fut = BoxFuture<S::Resp, S::Error>;
+
+    fn call(&self, req: S::Req) -> Self::Fut {
+        if !self.shared.sample() {
+            return self.primary.call(req).boxed();
+        }
+
+        self.shared.mirrored.fetch_add(1, Ordering::Relaxed);
+
+        let primary = self.primary.call(req.clone());
+
+        let shadow = match panic::catch_unwind(AssertUnwindSafe(|| self.shadow.call(req))) {
+            Ok(shadow) => shadow,
+            Err(_) => {
+                warn!("shadow service panicked");
+                return primary.boxed();
+            }
+        };
+
+        let compare = match self.compare {
+            Some(ref compare) => compare.clone(),
+            None => {
+                shadow.then(|res| {
+                    if res.is_err() {
+                        debug!("shadow call failed");
+                    }
+
+                    Ok::<(), ()>(())
+                }).forget();
+
+                return primary.boxed();
+            }
+        };
+
+        let (tx, rx) = futures::oneshot();
+        let shared = self.shared.clone();
+        let shadow_compare = compare.clone();
+
+        shadow.then(move |shadow_res| {
+            rx.then(move |primary_res| {
+                match (primary_res, shadow_res) {
+                    (Ok(Some(ref primary)), Ok(ref shadow)) => {
+                        if !(shadow_compare.matches)(primary, shadow) {
+                            warn!("mirrored response does not match primary response");
+                            shared.mismatches.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                    (_, Err(_)) => debug!("shadow call failed"),
+                    _ => {}
+                }
+
+                Ok::<(), ()>(())
+            })
+        }).forget();
+
+        primary.then(move |res| {
+            tx.complete(match res {
+                Ok(ref v) => Some((compare.snapshot)(v)),
+                Err(_) => None,
+            });
+
+            res
+        }).boxed()
+    }
+}
+
+impl<S, M> Clone for Mirror<S, M>
+    where S: Service + Clone,
+          M: Service + Clone,
+{
+    fn clone(&self) -> Mirror<S, M> {
+        Mirror {
+            primary: self.primary.clone(),
+            shadow: self.shadow.clone(),
+            compare: self.compare.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl Shared =====
+ *
+ */
+
+impl Shared {
+    // Returns true if the next request should be mirrored
+    fn sample(&self) -> bool {
+        let n = self.requests.fetch_add(1, Ordering::Relaxed) as f64;
+        ((n + 1.0) * self.rate).floor() > (n * self.rate).floor()
+    }
+}
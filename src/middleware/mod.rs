@@ -0,0 +1,25 @@
+//! Reusable `Service` middleware
+//!
+//! Middleware wrap an inner `Service` and are themselves a `Service`, which
+//! allows them to be composed and used anywhere a service is expected, for
+//! example with `pipeline::Server` or in front of a `pipeline::Client`.
+
+pub mod buffer;
+pub mod cache;
+pub mod circuit_breaker;
+pub mod coalesce;
+pub mod hedge;
+pub mod metrics;
+pub mod mirror;
+pub mod router;
+pub mod thread_pool;
+
+pub use self::buffer::Buffer;
+pub use self::cache::Cache;
+pub use self::circuit_breaker::CircuitBreaker;
+pub use self::coalesce::Coalesce;
+pub use self::hedge::Hedge;
+pub use self::metrics::Metrics;
+pub use self::mirror::Mirror;
+pub use self::router::Router;
+pub use self::thread_pool::ThreadPool;
@@ -0,0 +1,146 @@
+//! Dispatch requests to one of several services based on their content.
+//!
+//! A `Router` computes a key for each request using a user provided function
+//! and forwards the request to the service registered for that key. Requests
+//! whose key has no registered service are forwarded to a fallback service.
+//!
+//! Routes are stored as boxed trait objects, so services of different types
+//! may be registered as long as they agree on the request, response and error
+//! types.
+//!
+//! Cloning a `Router` is cheap, the clones share the registered services.
+//! This makes a `Router` usable as the `NewService` handed to a server. The
+//! routes are never modified once built, so the services must be `Sync` and
+//! are called concurrently by the clones.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use futures::{Future, BoxFuture};
+
+use Service;
+
+/// Routes each request to a service selected by the request's key.
+pub struct Router<K, F, Req, Resp, E> {
+    inner: Arc<Inner<K, F, Req, Resp, E>>,
+}
+
+/// Registers routes and creates a `Router`.
+pub struct Builder<K, F, Req, Resp, E> {
+    key: F,
+    routes: HashMap<K, Route<Req, Resp, E>>,
+}
+
+struct Inner<K, F, Req, Resp, E> {
+    key: F,
+    routes: HashMap<K, Route<Req, Resp, E>>,
+    fallback: Route<Req, Resp, E>,
+}
+
+// A registered service, boxing the futures it returns
+type Route<Req, Resp, E> = Box<Fn(Req) -> BoxFuture<Resp, E> + Send + Sync>;
+
+/*
+ *
+ * ===== impl Builder =====
+ *
+ */
+
+impl<K, F, Req, Resp, E> Builder<K, F, Req, Resp, E>
+    where K: Hash + Eq + Send + Sync + 'static,
+          F: Fn(&Req) -> K + Send + Sync + 'static,
+          Req: Send + 'static,
+          Resp: Send + 'static,
+          E: Send + 'static,
+{
+    /// Returns a new `Builder` routing requests by the key computed by `key`.
+    pub fn new(key: F) -> Builder<K, F, Req, Resp, E> {
+        Builder {
+            key: key,
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Route requests with the given key to `service`, replacing any service
+    /// previously registered for the key.
+    pub fn route<S>(mut self, key: K, service: S) -> Builder<K, F, Req, Resp, E>
+        where S: Service<Req = Req, Resp = Resp, Error = E> + Sync,
+    {
+        self.routes.insert(key, route(service));
+        self
+    }
+
+    /// Create the `Router`, forwarding requests that match no route to
+    /// `fallback`.
+    pub fn build<S>(self, fallback: S) -> Router<K, F, Req, Resp, E>
+        where S: Service<Req = Req, Resp = Resp, Error = E> + Sync,
+    {
+        let inner = Inner {
+            key: self.key,
+            routes: self.routes,
+            fallback: route(fallback),
+        };
+
+        Router { inner: Arc::new(inner) }
+    }
+}
+
+fn route<S: Service + Sync>(service: S) -> Route<S::Req, S::Resp, S::Error> {
+    Box::new(move |req| service.call(req).boxed())
+}
+
+/*
+ *
+ * ===== impl Router =====
+ *
+ */
+
+impl<K, F, Req, Resp, E> Router<K, F, Req, Resp, E>
+    where K: Hash + Eq + Send + Sync + 'static,
+          F: Fn(&Req) -> K + Send + Sync + 'static,
+          Req: Send + 'static,
+          Resp: Send + 'static,
+          E: Send + 'static,
+{
+    /// Returns the number of registered routes, not counting the fallback.
+    pub fn len(&self) -> usize {
+        self.inner.routes.len()
+    }
+
+    /// Returns true if no route is registered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K, F, Req, Resp, E> Service for Router<K, F, Req, Resp, E>
+    where K: Hash + Eq + Send + Sync + 'static,
+          F: Fn(&Req) -> K + Send + Sync + 'static,
+          Req: Send + 'static,
+          Resp: Send + 'static,
+          E: Send + 'static,
+{
+    type Req = Req;
+    type Resp = Resp;
+    type Error = E;
+    type Fut = BoxFuture<Resp, E>;
+
+    fn call(&self, req: Req) -> Self::Fut {
+        let key = (self.inner.key)(&req);
+
+        match self.inner.routes.get(&key) {
+            Some(route) => route(req),
+            None => {
+                trace!("no route for request; using fallback");
+                (self.inner.fallback)(req)
+            }
+        }
+    }
+}
+
+impl<K, F, Req, Resp, E> Clone for Router<K, F, Req, Resp, E> {
+    fn clone(&self) -> Router<K, F, Req, Resp, E> {
+        Router { inner: self.inner.clone() }
+    }
+}
@@ -0,0 +1,229 @@
+//! Run a blocking service on a pool of worker threads.
+//!
+//! `pipeline::Server` drives all of its connections from a single event loop
+//! thread, so a service performing blocking disk I/O or CPU heavy work in
+//! `call` stalls every other connection. Wrapping such a service in a
+//! `ThreadPool` moves the call, as well as waiting on the future it returns,
+//! onto one of the pool's worker threads. The returned future completes once
+//! the worker is done.
+//!
+//! Responses may complete out of order, which is fine when used with
+//! `pipeline::Server`: the pipeline always writes responses in the order the
+//! requests were received.
+//!
+//! If the inner service panics, the worker thread survives and the call
+//! completes with `ThreadPoolError::Panicked`.
+
+use std::{error, fmt, io, thread};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+
+use futures::{self, Future, BoxFuture};
+
+use Service;
+
+/// Executes calls to the inner service on a pool of worker threads.
+///
+/// Cloning a `ThreadPool` returns a new handle to the same service and worker
+/// threads. The threads shut down once all handles have been dropped.
+pub struct ThreadPool<S> {
+    inner: Arc<S>,
+    pool: Arc<Pool>,
+}
+
+/// Configures and creates a `ThreadPool`.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    threads: usize,
+    name_prefix: Option<String>,
+}
+
+/// Errors returned by a `ThreadPool` on its own behalf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadPoolError {
+    /// The inner service panicked while handling the call.
+    Panicked,
+    /// The worker threads have shut down.
+    Shutdown,
+}
+
+type Job = Box<FnOnce() + Send + 'static>;
+
+struct Pool {
+    tx: Mutex<mpsc::Sender<Job>>,
+}
+
+/*
+ *
+ * ===== impl Builder =====
+ *
+ */
+
+impl Builder {
+    /// Returns a new `Builder` spawning 4 worker threads.
+    pub fn new() -> Builder {
+        Builder {
+            threads: 4,
+            name_prefix: None,
+        }
+    }
+
+    /// Set the number of worker threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is zero.
+    pub fn threads(mut self, val: usize) -> Builder {
+        assert!(val > 0, "a thread pool requires at least one thread");
+        self.threads = val;
+        self
+    }
+
+    /// Set the prefix of the worker thread names. Threads are named by
+    /// appending their index to the prefix.
+    pub fn name_prefix<T: Into<String>>(mut self, val: T) -> Builder {
+        self.name_prefix = Some(val.into());
+        self
+    }
+
+    /// Spawn the worker threads and return a `ThreadPool` running calls to
+    /// the given service on them.
+    pub fn build<S>(self, inner: S) -> io::Result<ThreadPool<S>>
+        where S: Service + Sync,
+              S::Error: From<ThreadPoolError>,
+    {
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+
+        for i in 0..self.threads {
+            let rx = rx.clone();
+            let mut thread = thread::Builder::new();
+
+            if let Some(ref prefix) = self.name_prefix {
+                thread = thread.name(format!("{}{}", prefix, i));
+            }
+
+            try!(thread.spawn(move || work(rx)));
+        }
+
+        Ok(ThreadPool {
+            inner: Arc::new(inner),
+            pool: Arc::new(Pool { tx: Mutex::new(tx) }),
+        })
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+// Worker thread loop, runs jobs until the sending half is dropped
+fn work(rx: Arc<Mutex<mpsc::Receiver<Job>>>) {
+    loop {
+        let job = match rx.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+
+        job();
+    }
+}
+
+/*
+ *
+ * ===== impl ThreadPool =====
+ *
+ */
+
+impl<S> ThreadPool<S>
+    where S: Service + Sync,
+          S::Error: From<ThreadPoolError>,
+{
+    /// Create a new `ThreadPool` with the default configuration.
+    pub fn new(inner: S) -> io::Result<ThreadPool<S>> {
+        Builder::new().build(inner)
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S> Service for ThreadPool<S>
+    where S: Service + Sync,
+          S::Error: From<ThreadPoolError>,
+{
+    type Req = S::Req;
+    type Resp = S::Resp;
+    type Error = S::Error;
+    type Fut = BoxFuture<S::Resp, S::Error>;
+
+    fn call(&self, req: S::Req) -> Self::Fut {
+        let (tx, rx) = futures::oneshot();
+        let service = self.inner.clone();
+
+        let job: Job = Box::new(move || {
+            let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                service.call(req).wait()
+            }));
+
+            tx.complete(match res {
+                Ok(res) => res,
+                Err(_) => {
+                    error!("service panicked on thread pool");
+                    Err(ThreadPoolError::Panicked.into())
+                }
+            });
+        });
+
+        if self.pool.tx.lock().unwrap().send(job).is_err() {
+            return futures::failed(ThreadPoolError::Shutdown.into()).boxed();
+        }
+
+        rx.then(|res| {
+            match res {
+                Ok(res) => res,
+                Err(_) => Err(ThreadPoolError::Shutdown.into()),
+            }
+        }).boxed()
+    }
+}
+
+impl<S> Clone for ThreadPool<S> {
+    fn clone(&self) -> ThreadPool<S> {
+        ThreadPool {
+            inner: self.inner.clone(),
+            pool: self.pool.clone(),
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl ThreadPoolError =====
+ *
+ */
+
+impl fmt::Display for ThreadPoolError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", error::Error::description(self))
+    }
+}
+
+impl error::Error for ThreadPoolError {
+    fn description(&self) -> &str {
+        match *self {
+            ThreadPoolError::Panicked => "service panicked",
+            ThreadPoolError::Shutdown => "thread pool shut down",
+        }
+    }
+}
+
+impl From<ThreadPoolError> for io::Error {
+    fn from(err: ThreadPoolError) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, err)
+    }
+}
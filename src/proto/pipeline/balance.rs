@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::{cmp, io};
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::stream::Stream;
+use futures::{self, Future, Poll, BoxFuture};
+use tokio_core::LoopHandle;
+
+use Service;
+use discover::{Change, Discover};
+use super::{connect, health, Client, Error, Message, NewTransport};
+use super::health::{Checked, Health, HealthCheck};
+
+/// Balances requests across a dynamic set of endpoints.
+///
+/// The endpoints are provided by a `Discover` value, which is driven by a
+/// task on the event loop. When an endpoint is inserted, `new_transport` is
+/// called with its key and a pipeline `Client` is connected using the
+/// returned `NewTransport`. When an endpoint is removed, its client is
+/// dropped, which closes the connection once the requests in flight on it
+/// complete.
+///
+/// Each request is dispatched to the endpoint with the fewest requests in
+/// flight. Connections that have been closed are re-established on the next
+/// call. An endpoint whose connections keep closing without serving a
+/// request, for example because it refuses connections, is skipped for an
+/// exponentially increasing back-off period between reconnects.
+///
+/// Endpoints may additionally be probed periodically by configuring a
+/// `HealthCheck`, in which case requests are only dispatched to the endpoints
+/// that passed their recent probes.
+///
+/// The balancer is itself a `Service` and cloning it returns a new handle to
+/// the same set of endpoints. Discovery stops once all handles have been
+/// dropped.
+pub struct Balance<K, F, T, B, E>
+    where K: Hash + Eq + Clone + Send + 'static,
+          F: Fn(&K) -> T + Send + 'static,
+          T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    inner: Arc<Mutex<Inner<K, F, T, B, E>>>,
+}
+
+struct Inner<K, F, T, B, E>
+    where K: Hash + Eq + Clone + Send + 'static,
+          F: Fn(&K) -> T + Send + 'static,
+          T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    handle: LoopHandle,
+    new_transport: F,
+    endpoints: HashMap<K, Endpoint<T::In, T::Out, B, E>>,
+}
+
+struct Endpoint<Req, Resp, B, E>
+    where Req: Send + 'static,
+          Resp: Send + 'static,
+          B: Stream<Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    client: Client<Req, Resp, B, E>,
+    // Number of requests currently in flight on the endpoint
+    in_flight: Arc<AtomicUsize>,
+    // Number of reconnects since a request last succeeded on the endpoint
+    failures: Arc<AtomicUsize>,
+    // The endpoint is skipped until then while its connection is closed
+    retry_at: Option<Instant>,
+    health: Health,
+}
+
+// Task applying discovery changes to the balancer
+struct Update<D, F, T, B, E>
+    where D: Discover,
+          F: Fn(&D::Key) -> T + Send + 'static,
+          T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    discover: D,
+    inner: Weak<Mutex<Inner<D::Key, F, T, B, E>>>,
+}
+
+// Back-off after the first failed reconnect, doubled on each further failure
+const BACKOFF_BASE_MS: u64 = 100;
+
+// Upper bound of the back-off
+const BACKOFF_MAX_MS: u64 = 30_000;
+
+/*
+ *
+ * ===== impl Balance =====
+ *
+ */
+
+impl<K, F, T, B, E> Balance<K, F, T, B, E>
+    where K: Hash + Eq + Clone + Send + 'static,
+          F: Fn(&K) -> T + Send + 'static,
+          T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    /// Create a new `Balance` connecting to the endpoints yielded by
+    /// `discover`, using `new_transport` to create the transport factory for
+    /// each endpoint.
+    pub fn new<D>(handle: LoopHandle, discover: D, new_transport: F) -> Balance<K, F, T, B, E>
+        where D: Discover<Key = K>,
+    {
+        let inner = Arc::new(Mutex::new(Inner {
+            handle: handle.clone(),
+            new_transport: new_transport,
+            endpoints: HashMap::new(),
+        }));
+
+        let update = Update {
+            discover: discover,
+            inner: Arc::downgrade(&inner),
+        };
+
+        handle.add_loop_data(|_| update).flatten().forget();
+
+        Balance { inner: inner }
+    }
+
+    /// Actively check the health of the endpoints as configured by `check`.
+    ///
+    /// Unhealthy endpoints are kept and requests are dispatched to them
+    /// again once they pass their probes.
+    pub fn health_check(self, check: HealthCheck<T::In, T::Out>) -> Balance<K, F, T, B, E> {
+        let handle = self.inner.lock().unwrap().handle.clone();
+        health::spawn(handle, &self.inner, check);
+        self
+    }
+
+    /// Returns the number of endpoints currently known to the balancer.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().endpoints.len()
+    }
+
+    /// Returns the number of endpoints currently considered healthy.
+    pub fn healthy(&self) -> usize {
+        self.inner.lock().unwrap().endpoints.values()
+            .filter(|endpoint| endpoint.health.is_healthy())
+            .count()
+    }
+}
+
+impl<K, F, T, B, E> Service for Balance<K, F, T, B, E>
+    where K: Hash + Eq + Clone + Send + 'static,
+          F: Fn(&K) -> T + Send + 'static,
+          T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    type Req = Message<T::In, B>;
+    type Resp = T::Out;
+    type Error = E;
+    type Fut = BoxFuture<Self::Resp, E>;
+
+    fn call(&self, request: Self::Req) -> Self::Fut {
+        let mut inner = self.inner.lock().unwrap();
+
+        let key = match inner.checkout() {
+            Some(key) => key,
+            None => {
+                let err = io::Error::new(io::ErrorKind::NotConnected, "no endpoints available");
+                return futures::failed(Error::Io(err).into()).boxed();
+            }
+        };
+
+        if inner.endpoints[&key].client.is_closed() {
+            inner.retry(&key);
+        }
+
+        let endpoint = &inner.endpoints[&key];
+        let in_flight = endpoint.in_flight.clone();
+        let failures = endpoint.failures.clone();
+
+        in_flight.fetch_add(1, Ordering::Relaxed);
+
+        endpoint.client.call(request).then(move |res| {
+            in_flight.fetch_sub(1, Ordering::Relaxed);
+
+            if res.is_ok() {
+                failures.store(0, Ordering::Relaxed);
+            }
+
+            res
+        }).boxed()
+    }
+}
+
+impl<K, F, T, B, E> Clone for Balance<K, F, T, B, E>
+    where K: Hash + Eq + Clone + Send + 'static,
+          F: Fn(&K) -> T + Send + 'static,
+          T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    fn clone(&self) -> Balance<K, F, T, B, E> {
+        Balance { inner: self.inner.clone() }
+    }
+}
+
+/*
+ *
+ * ===== impl Inner =====
+ *
+ */
+
+impl<K, F, T, B, E> Inner<K, F, T, B, E>
+    where K: Hash + Eq + Clone + Send + 'static,
+          F: Fn(&K) -> T + Send + 'static,
+          T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    fn apply(&mut self, change: Change<K>) {
+        match change {
+            Change::Insert(key) => {
+                if !self.endpoints.contains_key(&key) {
+                    debug!("inserting endpoint; current={}", self.endpoints.len());
+                    self.reconnect(&key);
+                }
+            }
+            Change::Remove(key) => {
+                debug!("removing endpoint; current={}", self.endpoints.len());
+                self.endpoints.remove(&key);
+            }
+        }
+    }
+
+    // Returns the key of the healthy endpoint to dispatch the next request on.
+    fn checkout(&self) -> Option<K> {
+        let now = Instant::now();
+
+        self.endpoints.iter()
+            .filter(|&(_, endpoint)| endpoint.health.is_healthy() && endpoint.is_available(now))
+            .min_by_key(|&(_, endpoint)| endpoint.in_flight.load(Ordering::Relaxed))
+            .map(|(key, _)| key.clone())
+    }
+
+    // Reconnect to an endpoint whose connection was closed, backing off
+    // from it if the previous connection did not serve any request.
+    fn retry(&mut self, key: &K) {
+        self.reconnect(key);
+
+        let endpoint = self.endpoints.get_mut(key).unwrap();
+        let failures = endpoint.failures.fetch_add(1, Ordering::Relaxed);
+
+        if failures > 0 {
+            let backoff = backoff(failures);
+            debug!("endpoint connection keeps closing; backing off; ms={}", backoff);
+            endpoint.retry_at = Some(Instant::now() + Duration::from_millis(backoff));
+        }
+    }
+
+    // Connect to the endpoint, replacing any previous connection
+    fn reconnect(&mut self, key: &K) {
+        let client = connect(self.handle.clone(), (self.new_transport)(key));
+
+        let endpoint = match self.endpoints.remove(key) {
+            Some(endpoint) => Endpoint { client: client, .. endpoint },
+            None => {
+                Endpoint {
+                    client: client,
+                    in_flight: Arc::new(AtomicUsize::new(0)),
+                    failures: Arc::new(AtomicUsize::new(0)),
+                    retry_at: None,
+                    health: Health::new(),
+                }
+            }
+        };
+
+        self.endpoints.insert(key.clone(), endpoint);
+    }
+}
+
+impl<K, F, T, B, E> Checked<T::In, T::Out> for Inner<K, F, T, B, E>
+    where K: Hash + Eq + Clone + Send + 'static,
+          F: Fn(&K) -> T + Send + 'static,
+          T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    type Key = K;
+
+    fn probe(&mut self, check: &HealthCheck<T::In, T::Out>) -> Vec<(K, BoxFuture<bool, io::Error>)> {
+        let now = Instant::now();
+        let closed: Vec<K> = self.endpoints.iter()
+            .filter(|&(_, endpoint)| endpoint.client.is_closed() && endpoint.is_available(now))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        // Reopen closed connections so they can be probed, without counting
+        // it as a failure: only the probe outcome affects their health, and
+        // endpoints backing off are left alone.
+        for key in closed {
+            self.reconnect(&key);
+        }
+
+        self.endpoints.iter().map(|(key, endpoint)| {
+            (key.clone(), health::probe(&self.handle, &endpoint.client, check))
+        }).collect()
+    }
+
+    fn record(&mut self, key: &K, success: bool, check: &HealthCheck<T::In, T::Out>) {
+        if let Some(endpoint) = self.endpoints.get_mut(key) {
+            endpoint.health.record(success, check);
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl Endpoint =====
+ *
+ */
+
+impl<Req, Resp, B, E> Endpoint<Req, Resp, B, E>
+    where Req: Send + 'static,
+          Resp: Send + 'static,
+          B: Stream<Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    // Returns false while the connection is closed and the endpoint is
+    // backed off.
+    fn is_available(&self, now: Instant) -> bool {
+        if !self.client.is_closed() {
+            return true;
+        }
+
+        match self.retry_at {
+            Some(at) => now >= at,
+            None => true,
+        }
+    }
+}
+
+// Back-off in milliseconds after the given number of failed reconnects
+fn backoff(failures: usize) -> u64 {
+    let shift = cmp::min(failures - 1, 16) as u32;
+    cmp::min(BACKOFF_BASE_MS << shift, BACKOFF_MAX_MS)
+}
+
+/*
+ *
+ * ===== impl Update =====
+ *
+ */
+
+impl<D, F, T, B, E> Future for Update<D, F, T, B, E>
+    where D: Discover,
+          F: Fn(&D::Key) -> T + Send + 'static,
+          T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        loop {
+            // Stop discovery once the balancer is gone
+            let inner = match self.inner.upgrade() {
+                Some(inner) => inner,
+                None => return Poll::Ok(()),
+            };
+
+            match self.discover.poll() {
+                Poll::Ok(Some(change)) => inner.lock().unwrap().apply(change),
+                Poll::Ok(None) => {
+                    debug!("endpoint discovery done");
+                    return Poll::Ok(());
+                }
+                Poll::Err(e) => {
+                    warn!("endpoint discovery failed; err={}", e);
+                    return Poll::Err(e);
+                }
+                Poll::NotReady => return Poll::NotReady,
+            }
+        }
+    }
+}
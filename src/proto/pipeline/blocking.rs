@@ -0,0 +1,144 @@
+use std::io;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use futures::stream::Stream;
+use futures::{self, Future, Complete};
+use tokio_core::{Loop, LoopHandle};
+
+use Service;
+use super::{connect, Client, Error, Message, NewTransport};
+
+/// A synchronous facade over a pipeline `Client`.
+///
+/// A `BlockingClient` owns an event loop running on a background thread and
+/// connects a `Client` on it. Calls block the current thread until the
+/// response is received or the optional timeout elapses, which makes it
+/// usable from plain synchronous code such as command line tools and tests.
+///
+/// Dropping the `BlockingClient` shuts down the event loop, closing the
+/// connection, and waits for the background thread to exit.
+pub struct BlockingClient<T, B, E>
+    where T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    client: Client<T::In, T::Out, B, E>,
+    handle: LoopHandle,
+    timeout: Option<Duration>,
+    shutdown: Option<Complete<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<T, B, E> BlockingClient<T, B, E>
+    where T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    /// Spawn the background event loop and connect a client on it.
+    ///
+    /// `new_transport` is called with a handle to the background event loop,
+    /// which is needed to create transports registered with it, and returns
+    /// the `NewTransport` used to connect.
+    pub fn connect<F>(new_transport: F) -> io::Result<BlockingClient<T, B, E>>
+        where F: FnOnce(&LoopHandle) -> T,
+    {
+        let (shutdown, rx) = futures::oneshot();
+        let (tx, handle) = mpsc::channel();
+
+        let thread = try!(thread::Builder::new().name("tokio-blocking-client".to_string()).spawn(move || {
+            let mut lp = match Loop::new() {
+                Ok(lp) => lp,
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    return;
+                }
+            };
+
+            let _ = tx.send(Ok(lp.handle()));
+            let _ = lp.run(rx);
+
+            debug!("blocking client event loop shut down");
+        }));
+
+        let handle = match handle.recv() {
+            Ok(res) => try!(res),
+            Err(_) => return Err(io::Error::new(io::ErrorKind::Other, "event loop thread panicked")),
+        };
+
+        let client = connect(handle.clone(), new_transport(&handle));
+
+        Ok(BlockingClient {
+            client: client,
+            handle: handle,
+            timeout: None,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+
+    /// Send the request and block until the response is received.
+    ///
+    /// If a timeout is set and the response is not received in time, the
+    /// call fails with a `TimedOut` error.
+    pub fn call(&self, request: Message<T::In, B>) -> Result<T::Out, E> {
+        let response = self.client.call(request);
+
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return response.wait(),
+        };
+
+        let timer = self.handle.clone().timeout(timeout).flatten().then(|res| {
+            let err = match res {
+                Ok(()) => io::Error::new(io::ErrorKind::TimedOut, "request timed out"),
+                Err(e) => e,
+            };
+
+            Err::<T::Out, E>(Error::Io(err).into())
+        });
+
+        match response.select(timer).wait() {
+            Ok((resp, _)) => Ok(resp),
+            Err((e, _)) => Err(e),
+        }
+    }
+
+    /// Set how long calls wait for a response. `None`, the default, waits
+    /// indefinitely.
+    pub fn set_timeout(&mut self, val: Option<Duration>) {
+        self.timeout = val;
+    }
+
+    /// Returns the timeout applied to calls.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Returns true if the connection has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.client.is_closed()
+    }
+
+    /// Returns a handle to the background event loop.
+    pub fn handle(&self) -> &LoopHandle {
+        &self.handle
+    }
+}
+
+impl<T, B, E> Drop for BlockingClient<T, B, E>
+    where T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.complete(());
+        }
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use std::collections::VecDeque;
 use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use futures::stream::Stream;
 use futures::{self, Poll, Future, BoxFuture, Complete};
@@ -20,6 +22,7 @@ pub struct Client<Req, Resp, ReqBody, E>
           E: From<Error<E>> + Send + 'static,
 {
     tx: Sender<(Message<Req, ReqBody>, Complete<Result<Resp, E>>)>,
+    closed: Arc<AtomicBool>,
 }
 
 struct Dispatch<T, B, E>
@@ -29,8 +32,14 @@ struct Dispatch<T, B, E>
 {
     requests: Receiver<(Message<T::In, B>, Complete<Result<T::Out, E>>)>,
     in_flight: VecDeque<Complete<Result<T::Out, E>>>,
+    // Flags the client as closed once the dispatch goes away
+    _closed: Closed,
 }
 
+// Sets the shared flag when dropped. This happens either when the pipeline
+// task terminates or when the transport could not be created.
+struct Closed(Arc<AtomicBool>);
+
 /// Connect to the given `addr` and handle using the given Transport and protocol pipelining.
 pub fn connect<T, B, E>(handle: LoopHandle, new_transport: T)
         -> Client<T::In, T::Out, B, E>
@@ -39,6 +48,8 @@ pub fn connect<T, B, E>(handle: LoopHandle, new_transport: T)
           E: From<Error<E>> + Send + 'static,
 {
     let (tx, rx) = handle.clone().channel();
+    let closed = Arc::new(AtomicBool::new(false));
+    let guard = Closed(closed.clone());
 
     handle.add_loop_data(|_| {
         rx.and_then(move |rx| {
@@ -49,6 +60,7 @@ pub fn connect<T, B, E>(handle: LoopHandle, new_transport: T)
             let dispatch: Dispatch<T::Item, B, E> = Dispatch {
                 requests: rx,
                 in_flight: VecDeque::with_capacity(32),
+                _closed: guard,
             };
 
             // Create the pipeline with the dispatch and transport
@@ -57,7 +69,25 @@ pub fn connect<T, B, E>(handle: LoopHandle, new_transport: T)
         }).flatten()
     }).flatten().forget();
 
-    Client { tx: tx }
+    Client {
+        tx: tx,
+        closed: closed,
+    }
+}
+
+impl<Req, Resp, ReqBody, E> Client<Req, Resp, ReqBody, E>
+    where Req: Send + 'static,
+          Resp: Send + 'static,
+          ReqBody: Stream<Error = E>,
+          E: From<Error<E>> + Send + 'static,
+{
+    /// Returns true if the connection backing this client has been closed.
+    ///
+    /// Once closed, all further calls complete immediately with a broken pipe
+    /// error.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
 }
 
 impl<Req, Resp, ReqBody, E> Service for Client<Req, Resp, ReqBody, E>
@@ -74,10 +104,18 @@ impl<Req, Resp, ReqBody, E> Service for Client<Req, Resp, ReqBody, E>
     fn call(&self, request: Self::Req) -> Self::Fut {
         let (tx, rx) = futures::oneshot();
 
-        // TODO: handle error
-        self.tx.send((request, tx)).ok().unwrap();
+        if self.is_closed() || self.tx.send((request, tx)).is_err() {
+            let err = Error::Io(broken_pipe());
+            return futures::failed(err.into()).boxed();
+        }
 
-        rx.then(|t| t.unwrap()).boxed()
+        rx.then(|res| {
+            match res {
+                Ok(res) => res,
+                // The dispatch went away without completing the request
+                Err(_) => Err(Error::Io(broken_pipe()).into()),
+            }
+        }).boxed()
     }
 }
 
@@ -88,7 +126,10 @@ impl<Req, Resp, ReqBody, E> Clone for Client<Req, Resp, ReqBody, E>
           E: From<Error<E>> + Send + 'static,
 {
     fn clone(&self) -> Client<Req, Resp, ReqBody, E> {
-        Client { tx: self.tx.clone() }
+        Client {
+            tx: self.tx.clone(),
+            closed: self.closed.clone(),
+        }
     }
 }
 
@@ -155,6 +196,12 @@ impl<T, B, E> Drop for Dispatch<T, B, E>
     }
 }
 
+impl Drop for Closed {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
 fn broken_pipe() -> io::Error {
     io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe")
 }
@@ -0,0 +1,285 @@
+use std::io;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use futures::stream::Stream;
+use futures::{Future, Poll, BoxFuture};
+use tokio_core::LoopHandle;
+
+use Service;
+use super::{Client, Error, Message};
+
+/// Configures active health checking of the connections of a `Balance` or a
+/// `Pool`.
+///
+/// A first round of probes is sent when health checking starts, then every
+/// `interval`. A probe request created by `probe` is sent on each connection
+/// and succeeds if a response is received within the timeout and `predicate`
+/// returns true for it.
+///
+/// A connection is marked unhealthy after a number of consecutive failed
+/// probes and healthy again after a number of consecutive successful probes.
+/// Requests are only dispatched on healthy connections. New connections are
+/// considered healthy until probed otherwise.
+///
+/// A `Balance` keeps unhealthy endpoints around and resumes dispatching to
+/// them once they recover, while a `Pool` closes unhealthy connections and
+/// opens new ones as needed.
+pub struct HealthCheck<Req, Resp> {
+    interval: Duration,
+    timeout: Duration,
+    unhealthy_threshold: usize,
+    healthy_threshold: usize,
+    probe: Arc<Fn() -> Req + Send + Sync>,
+    predicate: Arc<Fn(&Resp) -> bool + Send + Sync>,
+}
+
+/// Health of a single connection.
+pub struct Health {
+    healthy: bool,
+    successes: usize,
+    failures: usize,
+}
+
+/// Implemented by the shared state of the health checked services.
+pub trait Checked<Req, Resp>: Send + 'static {
+    /// Identifies a connection across rounds of probes.
+    type Key: Send + 'static;
+
+    /// Send a probe on each connection. Returns futures resolving to whether
+    /// the probe succeeded, which are driven without holding the lock.
+    fn probe(&mut self, check: &HealthCheck<Req, Resp>) -> Vec<(Self::Key, BoxFuture<bool, io::Error>)>;
+
+    /// Record the outcome of a probe sent on the connection.
+    fn record(&mut self, key: &Self::Key, success: bool, check: &HealthCheck<Req, Resp>);
+}
+
+/// Task periodically probing the connections of a health checked service.
+pub struct Check<I, Req, Resp> {
+    inner: Weak<Mutex<I>>,
+    config: HealthCheck<Req, Resp>,
+    handle: LoopHandle,
+    // Pending until the next round of probes, `None` when one is due
+    timer: Option<BoxFuture<(), io::Error>>,
+}
+
+/// Start health checking `inner` on the event loop referenced by `handle`.
+///
+/// Checking stops once `inner` has been dropped.
+pub fn spawn<I, Req, Resp>(handle: LoopHandle, inner: &Arc<Mutex<I>>, check: HealthCheck<Req, Resp>)
+    where I: Checked<Req, Resp>,
+          Req: 'static,
+          Resp: 'static,
+{
+    let task = Check {
+        inner: Arc::downgrade(inner),
+        config: check,
+        handle: handle.clone(),
+        timer: None,
+    };
+
+    handle.add_loop_data(|_| task).flatten().forget();
+}
+
+/// Send a probe on `client`, resolving to whether it succeeded.
+pub fn probe<Req, Resp, B, E>(handle: &LoopHandle,
+                              client: &Client<Req, Resp, B, E>,
+                              check: &HealthCheck<Req, Resp>)
+                              -> BoxFuture<bool, io::Error>
+    where Req: Send + 'static,
+          Resp: Send + 'static,
+          B: Stream<Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    let predicate = check.predicate.clone();
+    let request = Message::WithoutBody((check.probe)());
+
+    let response = client.call(request).then(move |res| {
+        Ok::<bool, io::Error>(match res {
+            Ok(ref resp) => predicate(resp),
+            Err(_) => false,
+        })
+    });
+
+    let timeout = handle.clone().timeout(check.timeout).flatten().map(|_| false);
+
+    response.select(timeout).then(|res| {
+        match res {
+            Ok((success, _)) => Ok::<bool, io::Error>(success),
+            Err(_) => Ok(false),
+        }
+    }).boxed()
+}
+
+/*
+ *
+ * ===== impl HealthCheck =====
+ *
+ */
+
+impl<Req, Resp> HealthCheck<Req, Resp> {
+    /// Returns a new `HealthCheck` probing each connection every `interval`.
+    ///
+    /// By default, probes time out after `interval`, connections are marked
+    /// unhealthy after 3 consecutive failures and healthy after 2 consecutive
+    /// successes.
+    pub fn new<P, F>(interval: Duration, probe: P, predicate: F) -> HealthCheck<Req, Resp>
+        where P: Fn() -> Req + Send + Sync + 'static,
+              F: Fn(&Resp) -> bool + Send + Sync + 'static,
+    {
+        HealthCheck {
+            interval: interval,
+            timeout: interval,
+            unhealthy_threshold: 3,
+            healthy_threshold: 2,
+            probe: Arc::new(probe),
+            predicate: Arc::new(predicate),
+        }
+    }
+
+    /// Set how long to wait for the response to a probe before considering
+    /// it failed.
+    pub fn timeout(mut self, val: Duration) -> HealthCheck<Req, Resp> {
+        self.timeout = val;
+        self
+    }
+
+    /// Set the number of consecutive failed probes after which a connection
+    /// is marked unhealthy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is zero.
+    pub fn unhealthy_threshold(mut self, val: usize) -> HealthCheck<Req, Resp> {
+        assert!(val > 0, "threshold must be greater than zero");
+        self.unhealthy_threshold = val;
+        self
+    }
+
+    /// Set the number of consecutive successful probes after which an
+    /// unhealthy connection is marked healthy again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is zero.
+    pub fn healthy_threshold(mut self, val: usize) -> HealthCheck<Req, Resp> {
+        assert!(val > 0, "threshold must be greater than zero");
+        self.healthy_threshold = val;
+        self
+    }
+}
+
+impl<Req, Resp> Clone for HealthCheck<Req, Resp> {
+    fn clone(&self) -> HealthCheck<Req, Resp> {
+        HealthCheck {
+            interval: self.interval,
+            timeout: self.timeout,
+            unhealthy_threshold: self.unhealthy_threshold,
+            healthy_threshold: self.healthy_threshold,
+            probe: self.probe.clone(),
+            predicate: self.predicate.clone(),
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl Health =====
+ *
+ */
+
+impl Health {
+    /// Returns the health of a new connection.
+    pub fn new() -> Health {
+        Health {
+            healthy: true,
+            successes: 0,
+            failures: 0,
+        }
+    }
+
+    /// Returns true if requests may be dispatched on the connection.
+    pub fn is_healthy(&self) -> bool {
+        self.healthy
+    }
+
+    /// Record the outcome of a probe, updating the connection's health once
+    /// a threshold is reached.
+    pub fn record<Req, Resp>(&mut self, success: bool, check: &HealthCheck<Req, Resp>) {
+        if success {
+            self.failures = 0;
+            self.successes += 1;
+
+            if !self.healthy && self.successes >= check.healthy_threshold {
+                debug!("connection is healthy again");
+                self.healthy = true;
+            }
+        } else {
+            self.successes = 0;
+            self.failures += 1;
+
+            if self.healthy && self.failures >= check.unhealthy_threshold {
+                warn!("connection is unhealthy; failed probes={}", self.failures);
+                self.healthy = false;
+            }
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl Check =====
+ *
+ */
+
+impl<I, Req, Resp> Future for Check<I, Req, Resp>
+    where I: Checked<Req, Resp>,
+          Req: 'static,
+          Resp: 'static,
+{
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        loop {
+            if let Some(mut timer) = self.timer.take() {
+                match timer.poll() {
+                    Poll::Ok(()) => {}
+                    Poll::Err(e) => return Poll::Err(e),
+                    Poll::NotReady => {
+                        self.timer = Some(timer);
+                        return Poll::NotReady;
+                    }
+                }
+            }
+
+            // Stop checking once the service is gone
+            let inner = match self.inner.upgrade() {
+                Some(inner) => inner,
+                None => return Poll::Ok(()),
+            };
+
+            let probes = inner.lock().unwrap().probe(&self.config);
+
+            trace!("probing connections; count={}", probes.len());
+
+            for (key, probe) in probes {
+                let inner = self.inner.clone();
+                let config = self.config.clone();
+
+                probe.then(move |res| {
+                    let success = res.unwrap_or(false);
+
+                    if let Some(inner) = inner.upgrade() {
+                        inner.lock().unwrap().record(&key, success, &config);
+                    }
+
+                    Ok::<(), ()>(())
+                }).forget();
+            }
+
+            let timer = self.handle.clone().timeout(self.config.interval).flatten();
+            self.timer = Some(timer.boxed());
+        }
+    }
+}
@@ -23,13 +23,27 @@
 //! that reads and writes `Frame` messages. It operates on the transport
 //! following the rules of pipelining as described above and exposes the
 //! protocol using a `Service`.
-
+//!
+//! A single `Client` is bound to a single connection. When requests should be
+//! spread across several connections, or when the connection should be
+//! re-established after it closes, use a `Pool`. When the set of endpoints
+//! changes at runtime, use a `Balance` fed by a `discover::Discover` value.
+//! Synchronous code may use a `BlockingClient`, which runs its own event loop.
+
+mod balance;
+mod blocking;
 mod client;
+mod health;
 mod server;
 mod pipeline;
+pub mod pool;
 
+pub use self::balance::Balance;
+pub use self::blocking::BlockingClient;
 pub use self::client::{connect, Client};
-pub use self::server::Server;
+pub use self::health::HealthCheck;
+pub use self::pool::Pool;
+pub use self::server::{serve, Serve, Server};
 
 use Service;
 use io::{Readiness};
@@ -228,6 +228,11 @@ impl<S, T, E> Pipeline<S, T>
             }
             debug!("write in body done");
 
+            // Writing the end of the body may have filled the transport
+            if !self.transport.is_writable() {
+                break;
+            }
+
             // Write the next in-flight in message
             if let Some(resp) = self.dispatch.poll() {
                 try!(self.write_in_message(resp));
@@ -275,7 +280,13 @@ impl<S, T, E> Pipeline<S, T>
     fn write_in_body(&mut self) -> io::Result<bool> {
         trace!("write_in_body");
         if let Some(ref mut body) = self.in_body {
-            while self.transport.is_writable() {
+            loop {
+                // Keep the body until the transport accepts more frames
+                if !self.transport.is_writable() {
+                    debug!("transport not writable");
+                    return Ok(false);
+                }
+
                 match body.poll() {
                     Poll::Ok(Some(chunk)) => {
                         let r = try!(self.transport.write(Frame::Body(Some(chunk))));
@@ -286,6 +297,7 @@ impl<S, T, E> Pipeline<S, T>
                     Poll::Ok(None) => {
                         try!(self.transport.write(Frame::Body(None)));
                         // Response body flushed, let fall through
+                        break;
                     }
                     Poll::Err(_) => {
                         unimplemented!();
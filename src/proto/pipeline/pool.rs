@@ -0,0 +1,444 @@
+//! A pool of pipeline client connections.
+//!
+//! A `Pool` is configured with a `Builder`, which sets the minimum and
+//! maximum number of connections and how long a connection may stay idle
+//! before being closed. The connections may additionally be health checked
+//! by configuring a `HealthCheck` with `Pool::health_check`.
+
+use std::{cmp, io};
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::stream::Stream;
+use futures::{Future, Poll, BoxFuture};
+use tokio_core::LoopHandle;
+
+use Service;
+use super::{connect, health, Client, Error, Message, NewTransport};
+use super::health::{Checked, Health, HealthCheck};
+
+/// A pool of pipeline `Client` connections.
+///
+/// The pool owns a `NewTransport` and uses it to establish connections on
+/// demand. Between `min` and `max` connections are kept open. Each request is
+/// dispatched on the connection with the fewest requests in flight; a new
+/// connection is only opened when all existing connections are busy and the
+/// pool is not yet full.
+///
+/// Connections that have been closed are evicted on the next call. When an
+/// idle timeout is configured, connections that have been idle for longer are
+/// closed, as long as doing so does not take the pool below `min`
+/// connections. Idle connections are evicted by a task on the event loop, so
+/// they are closed even when the pool receives no calls. Connections marked
+/// unhealthy by a `HealthCheck` are evicted on the next call as well.
+///
+/// The pool is itself a `Service` and cloning it returns a new handle to the
+/// same set of connections.
+pub struct Pool<T, B, E>
+    where T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    inner: Arc<Mutex<Inner<T, B, E>>>,
+}
+
+/// Configures and creates a `Pool`.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    min: usize,
+    max: usize,
+    idle_timeout: Option<Duration>,
+}
+
+struct Inner<T, B, E>
+    where T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    handle: LoopHandle,
+    // `NewTransport` is not required to be `Sync`, so it is shared with the
+    // connect closures behind a mutex.
+    new_transport: Arc<Mutex<T>>,
+    conns: Vec<Conn<T::In, T::Out, B, E>>,
+    // Identifies the next connection, for health checks
+    next_id: usize,
+    config: Builder,
+}
+
+struct Conn<Req, Resp, B, E>
+    where Req: Send + 'static,
+          Resp: Send + 'static,
+          B: Stream<Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    id: usize,
+    client: Client<Req, Resp, B, E>,
+    // Number of requests currently in flight on the connection
+    in_flight: Arc<AtomicUsize>,
+    // Last time a request was dispatched on the connection
+    last_used: Instant,
+    health: Health,
+}
+
+// Task periodically evicting idle connections
+struct Sweep<T, B, E>
+    where T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    inner: Weak<Mutex<Inner<T, B, E>>>,
+    interval: Duration,
+    handle: LoopHandle,
+    timer: Option<BoxFuture<(), io::Error>>,
+}
+
+const DEFAULT_MAX: usize = 8;
+
+/*
+ *
+ * ===== impl Builder =====
+ *
+ */
+
+impl Builder {
+    /// Returns a new `Builder` with the default configuration.
+    ///
+    /// By default, no connections are opened eagerly, at most 8 connections
+    /// are kept open and idle connections are never evicted.
+    pub fn new() -> Builder {
+        Builder {
+            min: 0,
+            max: DEFAULT_MAX,
+            idle_timeout: None,
+        }
+    }
+
+    /// Set the minimum number of connections kept open by the pool.
+    pub fn min_connections(mut self, val: usize) -> Builder {
+        self.min = val;
+        self
+    }
+
+    /// Set the maximum number of connections opened by the pool.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is zero.
+    pub fn max_connections(mut self, val: usize) -> Builder {
+        assert!(val > 0, "a pool requires at least one connection");
+        self.max = val;
+        self
+    }
+
+    /// Close connections that have not been used for the given duration.
+    pub fn idle_timeout(mut self, val: Duration) -> Builder {
+        self.idle_timeout = Some(val);
+        self
+    }
+
+    /// Create the `Pool`, opening the minimum number of connections.
+    pub fn build<T, B, E>(self, handle: LoopHandle, new_transport: T) -> Pool<T, B, E>
+        where T: NewTransport<Error = E>,
+              B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+              E: From<Error<E>> + Send + 'static,
+    {
+        let idle_timeout = self.idle_timeout;
+
+        let mut inner = Inner {
+            handle: handle.clone(),
+            new_transport: Arc::new(Mutex::new(new_transport)),
+            conns: Vec::with_capacity(self.max),
+            next_id: 0,
+            config: self,
+        };
+
+        inner.fill();
+
+        let inner = Arc::new(Mutex::new(inner));
+
+        if let Some(interval) = idle_timeout {
+            let task = Sweep {
+                inner: Arc::downgrade(&inner),
+                interval: interval,
+                handle: handle.clone(),
+                timer: None,
+            };
+
+            handle.add_loop_data(|_| task).flatten().forget();
+        }
+
+        Pool { inner: inner }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+/*
+ *
+ * ===== impl Pool =====
+ *
+ */
+
+impl<T, B, E> Pool<T, B, E>
+    where T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    /// Create a new `Pool` with the default configuration.
+    pub fn new(handle: LoopHandle, new_transport: T) -> Pool<T, B, E> {
+        Builder::new().build(handle, new_transport)
+    }
+
+    /// Actively check the health of the connections as configured by
+    /// `check`.
+    ///
+    /// Unhealthy connections are closed and replaced by new connections as
+    /// needed.
+    pub fn health_check(self, check: HealthCheck<T::In, T::Out>) -> Pool<T, B, E> {
+        let handle = self.inner.lock().unwrap().handle.clone();
+        health::spawn(handle, &self.inner, check);
+        self
+    }
+
+    /// Returns the number of connections currently held by the pool.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().conns.len()
+    }
+
+    /// Returns the number of connections currently considered healthy.
+    pub fn healthy(&self) -> usize {
+        self.inner.lock().unwrap().conns.iter()
+            .filter(|conn| conn.health.is_healthy())
+            .count()
+    }
+
+    /// Returns true if the pool currently holds no connections.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T, B, E> Service for Pool<T, B, E>
+    where T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    type Req = Message<T::In, B>;
+    type Resp = T::Out;
+    type Error = E;
+    type Fut = BoxFuture<Self::Resp, E>;
+
+    fn call(&self, request: Self::Req) -> Self::Fut {
+        let (evicted, response, in_flight) = {
+            let mut inner = self.inner.lock().unwrap();
+
+            let evicted = inner.evict();
+            inner.fill();
+
+            let idx = inner.checkout();
+            let conn = &mut inner.conns[idx];
+
+            conn.last_used = Instant::now();
+            conn.in_flight.fetch_add(1, Ordering::Relaxed);
+
+            (evicted, conn.client.call(request), conn.in_flight.clone())
+        };
+
+        // Close the evicted connections once the lock is released
+        drop(evicted);
+
+        response.then(move |res| {
+            in_flight.fetch_sub(1, Ordering::Relaxed);
+            res
+        }).boxed()
+    }
+}
+
+impl<T, B, E> Clone for Pool<T, B, E>
+    where T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    fn clone(&self) -> Pool<T, B, E> {
+        Pool { inner: self.inner.clone() }
+    }
+}
+
+/*
+ *
+ * ===== impl Inner =====
+ *
+ */
+
+impl<T, B, E> Inner<T, B, E>
+    where T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    // Remove closed and unhealthy connections as well as connections that
+    // have been idle for too long, returning them.
+    fn evict(&mut self) -> Vec<Conn<T::In, T::Out, B, E>> {
+        let now = Instant::now();
+        let timeout = self.config.idle_timeout;
+        let expired = |conn: &Conn<T::In, T::Out, B, E>| {
+            timeout.map_or(false, |timeout| {
+                conn.is_idle() && now.duration_since(conn.last_used) >= timeout
+            })
+        };
+
+        // Leave the connections untouched in the common case
+        let stale = self.conns.iter().any(|conn| {
+            conn.client.is_closed() || !conn.health.is_healthy()
+        });
+
+        if !stale && (self.conns.len() <= self.config.min || !self.conns.iter().any(&expired)) {
+            return vec![];
+        }
+
+        let (mut evicted, open): (Vec<_>, Vec<_>) = self.conns.drain(..)
+            .partition(|conn| conn.client.is_closed() || !conn.health.is_healthy());
+
+        self.conns = open;
+
+        let mut excess = self.conns.len().saturating_sub(self.config.min);
+
+        for conn in self.conns.drain(..).collect::<Vec<_>>() {
+            if excess > 0 && expired(&conn) {
+                trace!("evicting idle pool connection");
+                excess -= 1;
+                evicted.push(conn);
+            } else {
+                self.conns.push(conn);
+            }
+        }
+
+        evicted
+    }
+
+    // Open connections until the pool holds at least `min` connections.
+    fn fill(&mut self) {
+        let min = cmp::min(self.config.min, self.config.max);
+
+        while self.conns.len() < min {
+            self.connect();
+        }
+    }
+
+    // Returns the index of the connection to dispatch the next request on.
+    fn checkout(&mut self) -> usize {
+        let best = self.conns.iter()
+            .enumerate()
+            .min_by_key(|&(_, conn)| conn.in_flight.load(Ordering::Relaxed))
+            .map(|(i, conn)| (i, conn.is_idle()));
+
+        match best {
+            Some((i, true)) => i,
+            Some((i, false)) if self.conns.len() >= self.config.max => i,
+            _ => {
+                self.connect();
+                self.conns.len() - 1
+            }
+        }
+    }
+
+    fn connect(&mut self) {
+        trace!("opening new pool connection; current={}", self.conns.len());
+
+        let new_transport = self.new_transport.clone();
+        let client = connect(self.handle.clone(), move || {
+            new_transport.lock().unwrap().new_transport()
+        });
+
+        self.conns.push(Conn {
+            id: self.next_id,
+            client: client,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            last_used: Instant::now(),
+            health: Health::new(),
+        });
+
+        self.next_id += 1;
+    }
+}
+
+impl<T, B, E> Checked<T::In, T::Out> for Inner<T, B, E>
+    where T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    type Key = usize;
+
+    fn probe(&mut self, check: &HealthCheck<T::In, T::Out>) -> Vec<(usize, BoxFuture<bool, io::Error>)> {
+        // Closed connections are replaced on the next call, there is no point
+        // in probing them.
+        self.conns.iter()
+            .filter(|conn| !conn.client.is_closed())
+            .map(|conn| (conn.id, health::probe(&self.handle, &conn.client, check)))
+            .collect()
+    }
+
+    fn record(&mut self, id: &usize, success: bool, check: &HealthCheck<T::In, T::Out>) {
+        if let Some(conn) = self.conns.iter_mut().find(|conn| conn.id == *id) {
+            conn.health.record(success, check);
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl Sweep =====
+ *
+ */
+
+impl<T, B, E> Future for Sweep<T, B, E>
+    where T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        loop {
+            if self.timer.is_none() {
+                let timer = self.handle.clone().timeout(self.interval).flatten();
+                self.timer = Some(timer.boxed());
+            }
+
+            match self.timer.as_mut().unwrap().poll() {
+                Poll::Ok(()) => {}
+                Poll::Err(e) => return Poll::Err(e),
+                Poll::NotReady => return Poll::NotReady,
+            }
+
+            self.timer = None;
+
+            // Stop sweeping once the pool is gone
+            let inner = match self.inner.upgrade() {
+                Some(inner) => inner,
+                None => return Poll::Ok(()),
+            };
+
+            // Dropping the evicted clients may run arbitrary code, so do it
+            // without holding the lock.
+            let evicted = inner.lock().unwrap().evict();
+            drop(evicted);
+        }
+    }
+}
+
+impl<Req, Resp, B, E> Conn<Req, Resp, B, E>
+    where Req: Send + 'static,
+          Resp: Send + 'static,
+          B: Stream<Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    fn is_idle(&self) -> bool {
+        self.in_flight.load(Ordering::Relaxed) == 0
+    }
+}
@@ -1,6 +1,7 @@
 use super::{pipeline, Error, Message, ServerService, Transport};
+use AsyncNewService;
 use std::collections::VecDeque;
-use std::io;
+use std::{io, mem};
 use futures::{Future, Poll};
 
 // TODO:
@@ -17,6 +18,30 @@ pub struct Server<S, T>
     inner: pipeline::Pipeline<Dispatch<S>, T>,
 }
 
+/// A server `Task` that waits for a service to be created before dispatching
+/// `Transport` messages to it using protocol pipelining.
+///
+/// Returned by `pipeline::serve`.
+pub struct Serve<F, T>
+    where F: Future<Error = io::Error>,
+          F::Item: ServerService,
+          T: Transport,
+{
+    state: State<F, T>,
+}
+
+enum State<F, T>
+    where F: Future<Error = io::Error>,
+          F::Item: ServerService,
+          T: Transport,
+{
+    // Waiting on the service, the transport is not read from until then.
+    Waiting(F, T),
+    Running(Server<F::Item, T>),
+    // Holds the error which ended the task, until it is returned
+    Done(Option<io::Error>),
+}
+
 struct Dispatch<S: ServerService> {
     // The service handling the connection
     service: S,
@@ -49,6 +74,67 @@ impl<T, S, E> Server<S, T>
     }
 }
 
+/// Dispatch `Transport` messages to the service created by `new_service`
+/// using protocol pipelining.
+///
+/// No requests are read from the transport until the service has been
+/// created. If creating the service fails, the connection is closed.
+pub fn serve<N, T, E>(new_service: &N, transport: T) -> Serve<N::Fut, T>
+    where N: AsyncNewService,
+          N::Item: ServerService<Req = T::Out, Resp = T::In, Body = T::BodyIn, Error = E>,
+          T: Transport<Error = E>,
+          E: From<Error<E>> + Send + 'static,
+{
+    Serve { state: State::Waiting(new_service.new_service(), transport) }
+}
+
+impl<F, T, E> Future for Serve<F, T>
+    where F: Future<Error = io::Error>,
+          F::Item: ServerService<Req = T::Out, Resp = T::In, Body = T::BodyIn, Error = E>,
+          T: Transport<Error = E>,
+          E: From<Error<E>> + Send + 'static,
+{
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        loop {
+            let next = match self.state {
+                State::Waiting(ref mut fut, _) => {
+                    match fut.poll() {
+                        Poll::Ok(service) => Ok(service),
+                        Poll::Err(e) => {
+                            debug!("failed to create service; err={:?}", e);
+                            Err(e)
+                        }
+                        Poll::NotReady => return Poll::NotReady,
+                    }
+                }
+                State::Running(ref mut server) => return server.poll(),
+                State::Done(ref mut err) => {
+                    return match err.take() {
+                        Some(e) => Poll::Err(e),
+                        None => Poll::Err(io::Error::new(io::ErrorKind::Other, "Serve polled after completion")),
+                    };
+                }
+            };
+
+            let transport = match mem::replace(&mut self.state, State::Done(None)) {
+                State::Waiting(_, transport) => transport,
+                _ => unreachable!(),
+            };
+
+            self.state = match next.and_then(|service| {
+                trace!("service created; starting pipeline");
+                Server::new(service, transport)
+            }) {
+                Ok(server) => State::Running(server),
+                Err(e) => State::Done(Some(e)),
+            };
+        }
+    }
+}
+
 impl<S> pipeline::Dispatch for Dispatch<S>
     where S: ServerService,
 {
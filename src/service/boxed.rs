@@ -0,0 +1,66 @@
+use futures::{Future, BoxFuture};
+
+use super::Service;
+
+/// A `Service` trait object whose future is boxed.
+///
+/// Boxing erases the concrete type of a service, which allows services of
+/// different types but with the same request, response and error types to be
+/// stored together, for example in a `Router`.
+pub struct BoxService<Req, Resp, E> {
+    inner: Box<Service<Req = Req, Resp = Resp, Error = E, Fut = BoxFuture<Resp, E>> + Send>,
+}
+
+// Boxes the futures returned by the wrapped service
+struct Boxed<S>(S);
+
+/*
+ *
+ * ===== impl BoxService =====
+ *
+ */
+
+impl<Req, Resp, E> BoxService<Req, Resp, E>
+    where Req: Send + 'static,
+          Resp: Send + 'static,
+          E: Send + 'static,
+{
+    /// Box the given service.
+    pub fn new<S>(service: S) -> BoxService<Req, Resp, E>
+        where S: Service<Req = Req, Resp = Resp, Error = E>,
+    {
+        BoxService { inner: Box::new(Boxed(service)) }
+    }
+}
+
+impl<Req, Resp, E> Service for BoxService<Req, Resp, E>
+    where Req: Send + 'static,
+          Resp: Send + 'static,
+          E: Send + 'static,
+{
+    type Req = Req;
+    type Resp = Resp;
+    type Error = E;
+    type Fut = BoxFuture<Resp, E>;
+
+    fn call(&self, req: Req) -> BoxFuture<Resp, E> {
+        self.inner.call(req)
+    }
+}
+
+/*
+ *
+ * ===== impl Boxed =====
+ *
+ */
+
+impl<S: Service> Service for Boxed<S> {
+    type Req = S::Req;
+    type Resp = S::Resp;
+    type Error = S::Error;
+    type Fut = BoxFuture<S::Resp, S::Error>;
+
+    fn call(&self, req: S::Req) -> Self::Fut {
+        self.0.call(req).boxed()
+    }
+}
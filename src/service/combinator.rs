@@ -0,0 +1,466 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use futures::{Future, IntoFuture, Poll};
+
+use super::{Service, BoxService};
+
+/// An extension trait for `Service` providing adapter combinators.
+///
+/// Each combinator returns a nameable type implementing `Service`. When the
+/// adapted service is `Clone`, so is the adapter, which means it may also be
+/// used as a `NewService` (for example, to hand to `pipeline::Server`).
+pub trait ServiceExt: Service + Sized {
+    /// Adapt the service to accept requests of a different type by converting
+    /// each request with `f` before it is passed to the service.
+    fn map_request<F, R>(self, f: F) -> MapRequest<Self, F, R>
+        where F: Fn(R) -> Self::Req + Send + Sync + 'static,
+              R: Send + 'static,
+    {
+        MapRequest {
+            inner: self,
+            f: Arc::new(f),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Convert each successful response of the service with `f`.
+    fn map_response<F, R>(self, f: F) -> MapResponse<Self, F>
+        where F: Fn(Self::Resp) -> R + Send + Sync + 'static,
+              R: Send + 'static,
+    {
+        MapResponse {
+            inner: self,
+            f: Arc::new(f),
+        }
+    }
+
+    /// Convert each error returned by the service with `f`.
+    fn map_err<F, E>(self, f: F) -> MapErr<Self, F>
+        where F: Fn(Self::Error) -> E + Send + Sync + 'static,
+              E: Send + 'static,
+    {
+        MapErr {
+            inner: self,
+            f: Arc::new(f),
+        }
+    }
+
+    /// Chain a computation onto each successful response of the service.
+    ///
+    /// `f` is called with the response and returns a future resolving to the
+    /// final response. Errors returned by the service are passed through
+    /// without calling `f`.
+    fn and_then<F, B>(self, f: F) -> AndThen<Self, F>
+        where F: Fn(Self::Resp) -> B + Send + Sync + 'static,
+              B: IntoFuture<Error = Self::Error> + 'static,
+              B::Item: Send + 'static,
+              B::Future: Send + 'static,
+    {
+        AndThen {
+            inner: self,
+            f: Arc::new(f),
+        }
+    }
+
+    /// Chain a computation onto the completion of each call, successful or
+    /// not.
+    ///
+    /// `f` is called with the result of the call and returns a future
+    /// resolving to the final result.
+    fn then<F, B>(self, f: F) -> Then<Self, F>
+        where F: Fn(Result<Self::Resp, Self::Error>) -> B + Send + Sync + 'static,
+              B: IntoFuture + 'static,
+              B::Item: Send + 'static,
+              B::Error: Send + 'static,
+              B::Future: Send + 'static,
+    {
+        Then {
+            inner: self,
+            f: Arc::new(f),
+        }
+    }
+
+    /// Box the service, erasing its concrete type.
+    fn boxed(self) -> BoxService<Self::Req, Self::Resp, Self::Error> {
+        BoxService::new(self)
+    }
+}
+
+impl<S: Service> ServiceExt for S {
+}
+
+/// Service returned by `ServiceExt::map_request`.
+pub struct MapRequest<S, F, R> {
+    inner: S,
+    f: Arc<F>,
+    _marker: PhantomData<fn(R)>,
+}
+
+/// Service returned by `ServiceExt::map_response`.
+pub struct MapResponse<S, F> {
+    inner: S,
+    f: Arc<F>,
+}
+
+/// Future returned by `MapResponse`.
+pub struct MapResponseFuture<T, F> {
+    fut: T,
+    f: Arc<F>,
+}
+
+/// Service returned by `ServiceExt::map_err`.
+pub struct MapErr<S, F> {
+    inner: S,
+    f: Arc<F>,
+}
+
+/// Future returned by `MapErr`.
+pub struct MapErrFuture<T, F> {
+    fut: T,
+    f: Arc<F>,
+}
+
+/// Service returned by `ServiceExt::and_then`.
+pub struct AndThen<S, F> {
+    inner: S,
+    f: Arc<F>,
+}
+
+/// Future returned by `AndThen`.
+pub struct AndThenFuture<T, B, F>
+    where B: IntoFuture,
+{
+    state: Chain<T, B::Future, F>,
+}
+
+/// Service returned by `ServiceExt::then`.
+pub struct Then<S, F> {
+    inner: S,
+    f: Arc<F>,
+}
+
+/// Future returned by `Then`.
+pub struct ThenFuture<T, B, F>
+    where B: IntoFuture,
+{
+    state: Chain<T, B::Future, F>,
+}
+
+// Shared state machine for `AndThenFuture` and `ThenFuture`
+enum Chain<A, B, F> {
+    First(A, Arc<F>),
+    Second(B),
+    Done,
+}
+
+/*
+ *
+ * ===== impl MapRequest =====
+ *
+ */
+
+impl<S, F, R> MapRequest<S, F, R> {
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, F, R> Service for MapRequest<S, F, R>
+    where S: Service,
+          F: Fn(R) -> S::Req + Send + Sync + 'static,
+          R: Send + 'static,
+{
+    type Req = R;
+    type Resp = S::Resp;
+    type Error = S::Error;
+    type Fut = S::Fut;
+
+    fn call(&self, req: R) -> S::Fut {
+        self.inner.call((self.f)(req))
+    }
+}
+
+impl<S: Clone, F, R> Clone for MapRequest<S, F, R> {
+    fn clone(&self) -> MapRequest<S, F, R> {
+        MapRequest {
+            inner: self.inner.clone(),
+            f: self.f.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl MapResponse =====
+ *
+ */
+
+impl<S, F> MapResponse<S, F> {
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, F, R> Service for MapResponse<S, F>
+    where S: Service,
+          F: Fn(S::Resp) -> R + Send + Sync + 'static,
+          R: Send + 'static,
+{
+    type Req = S::Req;
+    type Resp = R;
+    type Error = S::Error;
+    type Fut = MapResponseFuture<S::Fut, F>;
+
+    fn call(&self, req: S::Req) -> Self::Fut {
+        MapResponseFuture {
+            fut: self.inner.call(req),
+            f: self.f.clone(),
+        }
+    }
+}
+
+impl<S: Clone, F> Clone for MapResponse<S, F> {
+    fn clone(&self) -> MapResponse<S, F> {
+        MapResponse {
+            inner: self.inner.clone(),
+            f: self.f.clone(),
+        }
+    }
+}
+
+impl<T, F, R> Future for MapResponseFuture<T, F>
+    where T: Future,
+          F: Fn(T::Item) -> R,
+{
+    type Item = R;
+    type Error = T::Error;
+
+    fn poll(&mut self) -> Poll<R, T::Error> {
+        match self.fut.poll() {
+            Poll::Ok(v) => Poll::Ok((self.f)(v)),
+            Poll::Err(e) => Poll::Err(e),
+            Poll::NotReady => Poll::NotReady,
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl MapErr =====
+ *
+ */
+
+impl<S, F> MapErr<S, F> {
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, F, E> Service for MapErr<S, F>
+    where S: Service,
+          F: Fn(S::Error) -> E + Send + Sync + 'static,
+          E: Send + 'static,
+{
+    type Req = S::Req;
+    type Resp = S::Resp;
+    type Error = E;
+    type Fut = MapErrFuture<S::Fut, F>;
+
+    fn call(&self, req: S::Req) -> Self::Fut {
+        MapErrFuture {
+            fut: self.inner.call(req),
+            f: self.f.clone(),
+        }
+    }
+}
+
+impl<S: Clone, F> Clone for MapErr<S, F> {
+    fn clone(&self) -> MapErr<S, F> {
+        MapErr {
+            inner: self.inner.clone(),
+            f: self.f.clone(),
+        }
+    }
+}
+
+impl<T, F, E> Future for MapErrFuture<T, F>
+    where T: Future,
+          F: Fn(T::Error) -> E,
+{
+    type Item = T::Item;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<T::Item, E> {
+        match self.fut.poll() {
+            Poll::Ok(v) => Poll::Ok(v),
+            Poll::Err(e) => Poll::Err((self.f)(e)),
+            Poll::NotReady => Poll::NotReady,
+        }
+    }
+}
+
+/*
+ *
+ * ===== impl AndThen =====
+ *
+ */
+
+impl<S, F> AndThen<S, F> {
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, F, B> Service for AndThen<S, F>
+    where S: Service,
+          F: Fn(S::Resp) -> B + Send + Sync + 'static,
+          B: IntoFuture<Error = S::Error> + 'static,
+          B::Item: Send + 'static,
+          B::Future: Send + 'static,
+{
+    type Req = S::Req;
+    type Resp = B::Item;
+    type Error = S::Error;
+    type Fut = AndThenFuture<S::Fut, B, F>;
+
+    fn call(&self, req: S::Req) -> Self::Fut {
+        AndThenFuture {
+            state: Chain::First(self.inner.call(req), self.f.clone()),
+        }
+    }
+}
+
+impl<S: Clone, F> Clone for AndThen<S, F> {
+    fn clone(&self) -> AndThen<S, F> {
+        AndThen {
+            inner: self.inner.clone(),
+            f: self.f.clone(),
+        }
+    }
+}
+
+impl<T, B, F> Future for AndThenFuture<T, B, F>
+    where T: Future,
+          B: IntoFuture<Error = T::Error>,
+          F: Fn(T::Item) -> B,
+{
+    type Item = B::Item;
+    type Error = B::Error;
+
+    fn poll(&mut self) -> Poll<B::Item, B::Error> {
+        self.state.poll(|res, f| {
+            match res {
+                Ok(v) => Ok(f(v).into_future()),
+                Err(e) => Err(e),
+            }
+        })
+    }
+}
+
+/*
+ *
+ * ===== impl Then =====
+ *
+ */
+
+impl<S, F> Then<S, F> {
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, F, B> Service for Then<S, F>
+    where S: Service,
+          F: Fn(Result<S::Resp, S::Error>) -> B + Send + Sync + 'static,
+          B: IntoFuture + 'static,
+          B::Item: Send + 'static,
+          B::Error: Send + 'static,
+          B::Future: Send + 'static,
+{
+    type Req = S::Req;
+    type Resp = B::Item;
+    type Error = B::Error;
+    type Fut = ThenFuture<S::Fut, B, F>;
+
+    fn call(&self, req: S::Req) -> Self::Fut {
+        ThenFuture {
+            state: Chain::First(self.inner.call(req), self.f.clone()),
+        }
+    }
+}
+
+impl<S: Clone, F> Clone for Then<S, F> {
+    fn clone(&self) -> Then<S, F> {
+        Then {
+            inner: self.inner.clone(),
+            f: self.f.clone(),
+        }
+    }
+}
+
+impl<T, B, F> Future for ThenFuture<T, B, F>
+    where T: Future,
+          B: IntoFuture,
+          F: Fn(Result<T::Item, T::Error>) -> B,
+{
+    type Item = B::Item;
+    type Error = B::Error;
+
+    fn poll(&mut self) -> Poll<B::Item, B::Error> {
+        self.state.poll(|res, f| Ok(f(res).into_future()))
+    }
+}
+
+/*
+ *
+ * ===== impl Chain =====
+ *
+ */
+
+impl<A, B, F> Chain<A, B, F>
+    where A: Future,
+          B: Future,
+{
+    // Drive the first future to completion, use `next` to compute the second
+    // future from its result, then drive the second future to completion.
+    fn poll<N>(&mut self, next: N) -> Poll<B::Item, B::Error>
+        where N: FnOnce(Result<A::Item, A::Error>, &F) -> Result<B, B::Error>,
+    {
+        let res = match *self {
+            Chain::First(ref mut a, _) => {
+                match a.poll() {
+                    Poll::Ok(v) => Ok(v),
+                    Poll::Err(e) => Err(e),
+                    Poll::NotReady => return Poll::NotReady,
+                }
+            }
+            Chain::Second(ref mut b) => return b.poll(),
+            Chain::Done => panic!("cannot poll a chained future twice"),
+        };
+
+        let f = match ::std::mem::replace(self, Chain::Done) {
+            Chain::First(_, f) => f,
+            _ => unreachable!(),
+        };
+
+        let mut b = match next(res, &*f) {
+            Ok(b) => b,
+            Err(e) => return Poll::Err(e),
+        };
+
+        match b.poll() {
+            Poll::NotReady => {
+                *self = Chain::Second(b);
+                Poll::NotReady
+            }
+            ready => ready,
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Definition of the core `Service` trait along with helpers for creating and
+//! adapting services.
+//!
+//! The `ServiceExt` trait provides combinators for adapting a service's
+//! request, response and error types without writing a wrapper by hand.
+//! Services of different types may be stored together by boxing them into a
+//! `BoxService`.
+//!
+//! Services are created by a `NewService` factory. When creating the service
+//! requires performing I/O, for example opening a database connection, use
+//! `AsyncNewService` instead, which returns a future resolving to the service.
+
+mod boxed;
+mod combinator;
+
+pub use self::boxed::BoxService;
+pub use self::combinator::{ServiceExt, MapRequest, MapResponse, MapResponseFuture,
+                           MapErr, MapErrFuture, AndThen, AndThenFuture, Then, ThenFuture};
+
+use std::io;
+use std::sync::Arc;
+
+use futures::{Future, IntoFuture};
+
+pub use tokio_service::{Service, SimpleService, simple_service};
+
+/// Creates new `Service` values.
+pub trait NewService {
+
+    /// Requests handled by the service
+    type Req: Send + 'static;
+
+    /// Responses given by the service
+    type Resp: Send + 'static;
+
+    /// Errors produced by the service
+    type Error: Send + 'static;
+
+    /// The `Service` value created by this factory
+    type Item: Service<Req = Self::Req, Resp = Self::Resp, Error = Self::Error>;
+
+    /// Create and return a new service value.
+    fn new_service(&self) -> io::Result<Self::Item>;
+}
+
+impl<T> NewService for T
+    where T: Service + Clone,
+{
+    type Item = T;
+    type Req = T::Req;
+    type Resp = T::Resp;
+    type Error = T::Error;
+
+    fn new_service(&self) -> io::Result<T> {
+        Ok(self.clone())
+    }
+}
+
+/// Asynchronously creates new `Service` values.
+///
+/// This is the future-returning counterpart of `NewService`. It is useful
+/// when creating the service requires performing I/O, as it does not block
+/// the event loop while the service is being created.
+pub trait AsyncNewService {
+
+    /// Requests handled by the service
+    type Req: Send + 'static;
+
+    /// Responses given by the service
+    type Resp: Send + 'static;
+
+    /// Errors produced by the service
+    type Error: Send + 'static;
+
+    /// The `Service` value created by this factory
+    type Item: Service<Req = Self::Req, Resp = Self::Resp, Error = Self::Error>;
+
+    /// The future `Service` value
+    type Fut: Future<Item = Self::Item, Error = io::Error>;
+
+    /// Create and return a future resolving to a new service value.
+    fn new_service(&self) -> Self::Fut;
+}
+
+impl<F, R, S> AsyncNewService for F
+    where F: Fn() -> R,
+          R: IntoFuture<Item = S, Error = io::Error>,
+          S: Service,
+{
+    type Item = S;
+    type Req = S::Req;
+    type Resp = S::Resp;
+    type Error = S::Error;
+    type Fut = R::Future;
+
+    fn new_service(&self) -> R::Future {
+        self().into_future()
+    }
+}
+
+impl<T> AsyncNewService for Arc<T>
+    where T: AsyncNewService,
+{
+    type Item = T::Item;
+    type Req = T::Req;
+    type Resp = T::Resp;
+    type Error = T::Error;
+    type Fut = T::Fut;
+
+    fn new_service(&self) -> T::Fut {
+        (**self).new_service()
+    }
+}
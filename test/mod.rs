@@ -1,3 +1,4 @@
+extern crate bytes;
 extern crate env_logger;
 extern crate futures;
 extern crate lazycell;
@@ -12,6 +13,9 @@ extern crate log;
 mod support;
 
 // Tests
+mod test_discover;
 mod test_proto;
 mod test_io;
+mod test_middleware;
 mod test_server;
+mod test_service;
@@ -1,4 +1,5 @@
 pub mod mock;
+pub mod stream;
 
 use std::time::Duration;
 
@@ -12,3 +13,17 @@ pub fn sleep_ms(ms: u64) {
 pub fn millis(ms: u64) -> Duration {
     Duration::from_millis(ms)
 }
+
+/// Block the thread until `f` returns true, panicking if it does not within
+/// a few seconds
+pub fn wait_for<F: FnMut() -> bool>(mut f: F) {
+    for _ in 0..500 {
+        if f() {
+            return;
+        }
+
+        sleep_ms(10);
+    }
+
+    panic!("condition not met in time");
+}
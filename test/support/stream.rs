@@ -0,0 +1,129 @@
+use std::{cmp, io};
+use std::sync::{Arc, Mutex};
+
+use tokio_proto::io::Readiness;
+
+/// In-memory stream for testing framed transports.
+///
+/// Clones share the same buffers, so a clone may be kept by the test to feed
+/// bytes to and inspect bytes written by the transport.
+#[derive(Clone)]
+pub struct Stream {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    // Bytes the stream will return from reads
+    rd: Vec<u8>,
+    // Reads return 0 once `rd` is drained
+    eof: bool,
+    // Bytes written to the stream
+    wr: Vec<u8>,
+    // Number of bytes that can be written before blocking
+    wr_cap: Option<usize>,
+    // Number of calls to `write`
+    writes: usize,
+}
+
+impl Stream {
+    pub fn new() -> Stream {
+        Stream {
+            inner: Arc::new(Mutex::new(Inner {
+                rd: vec![],
+                eof: false,
+                wr: vec![],
+                wr_cap: None,
+                writes: 0,
+            })),
+        }
+    }
+
+    /// Make the given bytes available to read
+    pub fn feed(&self, bytes: &[u8]) {
+        self.inner.lock().unwrap().rd.extend_from_slice(bytes);
+    }
+
+    /// Reads return 0 once all fed bytes have been read
+    pub fn close(&self) {
+        self.inner.lock().unwrap().eof = true;
+    }
+
+    /// Set the number of bytes accepted before writes would block, `None`
+    /// accepts all writes
+    pub fn set_write_capacity(&self, cap: Option<usize>) {
+        self.inner.lock().unwrap().wr_cap = cap;
+    }
+
+    /// Take the bytes written so far
+    pub fn written(&self) -> Vec<u8> {
+        let mut inner = self.inner.lock().unwrap();
+        ::std::mem::replace(&mut inner.wr, vec![])
+    }
+
+    /// Returns the number of calls to `write` made so far
+    pub fn writes(&self) -> usize {
+        self.inner.lock().unwrap().writes
+    }
+}
+
+impl io::Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.rd.is_empty() {
+            if inner.eof {
+                return Ok(0);
+            }
+
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "would block"));
+        }
+
+        let n = cmp::min(buf.len(), inner.rd.len());
+        buf[..n].copy_from_slice(&inner.rd[..n]);
+        inner.rd.drain(..n);
+
+        Ok(n)
+    }
+}
+
+impl io::Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Inner {
+    // Counts as a single system call
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writes += 1;
+
+        let n = match self.wr_cap {
+            Some(0) => return Err(io::Error::new(io::ErrorKind::WouldBlock, "would block")),
+            Some(cap) => cmp::min(cap, buf.len()),
+            None => buf.len(),
+        };
+
+        if let Some(ref mut cap) = self.wr_cap {
+            *cap -= n;
+        }
+
+        self.wr.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl Readiness for Stream {
+    fn is_readable(&self) -> bool {
+        let inner = self.inner.lock().unwrap();
+        !inner.rd.is_empty() || inner.eof
+    }
+
+    fn is_writable(&self) -> bool {
+        self.inner.lock().unwrap().wr_cap != Some(0)
+    }
+}
@@ -0,0 +1,98 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::sync::mpsc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::stream::Stream;
+use futures::{Future, oneshot};
+use support;
+use tokio_proto::discover::{Change, FileList};
+use tokio_core::{Loop, LoopHandle};
+
+#[test]
+fn test_file_list_yields_changes() {
+    let _ = ::env_logger::init();
+
+    let (tx, rx) = oneshot();
+    let (tx2, rx2) = mpsc::channel();
+    let t = thread::spawn(move || {
+        let mut lp = Loop::new().unwrap();
+        tx2.send(lp.handle()).unwrap();
+        lp.run(rx)
+    });
+
+    let handle: LoopHandle = rx2.recv().unwrap();
+
+    let file = TempFile::new("file-list");
+    let path = &file.0;
+    write(path, "# endpoints\n127.0.0.1:8080\n\n127.0.0.1:8081\n127.0.0.1:8080\nnot-an-address\n");
+
+    let list = FileList::new(handle, path, support::millis(10)).unwrap();
+
+    let (change, list) = next(list);
+    assert_eq!(Change::Insert(addr("127.0.0.1:8080")), change);
+
+    let (change, list) = next(list);
+    assert_eq!(Change::Insert(addr("127.0.0.1:8081")), change);
+
+    write(path, "127.0.0.1:8081\n127.0.0.1:8082\n");
+
+    let (change, list) = next(list);
+    assert_eq!(Change::Remove(addr("127.0.0.1:8080")), change);
+
+    let (change, list) = next(list);
+    assert_eq!(Change::Insert(addr("127.0.0.1:8082")), change);
+
+    // An endpoint removed and then listed again is inserted again
+    write(path, "127.0.0.1:8081\n");
+
+    let (change, list) = next(list);
+    assert_eq!(Change::Remove(addr("127.0.0.1:8082")), change);
+
+    write(path, "127.0.0.1:8081\n127.0.0.1:8082\n");
+
+    let (change, _) = next(list);
+    assert_eq!(Change::Insert(addr("127.0.0.1:8082")), change);
+
+    tx.complete(());
+    t.join().unwrap().unwrap();
+}
+
+fn next(list: FileList) -> (Change<SocketAddr>, FileList) {
+    match list.into_future().wait() {
+        Ok((Some(change), list)) => (change, list),
+        Ok((None, _)) => panic!("file list done"),
+        Err((e, _)) => panic!("file list failed; err={}", e),
+    }
+}
+
+// A file in the temp directory, removed when dropped
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> TempFile {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let name = format!("tokio-proto-test-{}-{}-{}", name, now.as_secs(), now.subsec_nanos());
+
+        TempFile(env::temp_dir().join(name))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn write(path: &Path, contents: &str) {
+    let mut file = File::create(path).unwrap();
+    file.write_all(contents.as_bytes()).unwrap();
+}
+
+fn addr(s: &str) -> SocketAddr {
+    s.parse().unwrap()
+}
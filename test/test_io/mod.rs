@@ -1 +1,5 @@
+mod test_codec;
+mod test_delimited;
+mod test_framed;
+mod test_length_delimited;
 mod test_ready;
@@ -0,0 +1,83 @@
+use std::io as std_io;
+
+use bytes::{BlockBuf, MutBuf};
+use support::stream::Stream;
+use tokio_proto::io::{self, Codec, Transport};
+use tokio_proto::io::codec::{self, length_delimited};
+
+// Newline terminated frames. Once the peer sends "upper", written frames are
+// upper cased.
+struct Negotiated {
+    upper: bool,
+}
+
+impl Codec for Negotiated {
+    type Out = String;
+    type In = String;
+
+    fn decode(&mut self, buf: &mut BlockBuf) -> std_io::Result<Option<String>> {
+        buf.compact();
+
+        let pos = match buf.bytes().and_then(|bytes| bytes.iter().position(|b| *b == b'\n')) {
+            Some(pos) => pos,
+            None => return Ok(None),
+        };
+
+        let line = String::from_utf8_lossy(&buf.bytes().unwrap()[..pos]).into_owned();
+        buf.drop(pos + 1);
+
+        if line == "upper" {
+            self.upper = true;
+        }
+
+        Ok(Some(line))
+    }
+
+    fn encode(&mut self, msg: String, buf: &mut BlockBuf) -> std_io::Result<()> {
+        let msg = if self.upper { msg.to_uppercase() } else { msg };
+
+        buf.write_slice(msg.as_bytes());
+        buf.write_slice(b"\n");
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_framed_shares_codec_state() {
+    let stream = Stream::new();
+    let mut transport = io::Stream::framed(stream.clone(), Negotiated { upper: false });
+
+    transport.write("a".to_string()).unwrap();
+
+    stream.feed(b"upper\n");
+    assert_eq!(Some("upper".to_string()), transport.read().unwrap());
+
+    transport.write("b".to_string()).unwrap();
+
+    assert_eq!(Some(()), transport.flush().unwrap());
+    assert_eq!(b"a\nB\n".to_vec(), stream.written());
+}
+
+#[test]
+fn test_parse_serialize_pair_is_codec() {
+    let builder = length_delimited::Builder::new().length_field(length_delimited::LengthField::U8);
+    let mut codec = (builder.new_parser(), builder.new_serializer());
+
+    let mut buf = BlockBuf::default();
+    codec.encode(b"ab".to_vec(), &mut buf).unwrap();
+
+    assert_eq!(3, buf.len());
+    assert_eq!(Some(2), codec.decode(&mut buf).unwrap().map(|frame| frame.len()));
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn test_split_codec_is_send() {
+    fn assert_send<T: Send>(_: &T) {}
+
+    let (parse, serialize) = codec::split(Negotiated { upper: false });
+
+    assert_send(&parse);
+    assert_send(&serialize);
+}
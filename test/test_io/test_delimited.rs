@@ -0,0 +1,137 @@
+use std::io as std_io;
+
+use bytes::{Buf, Bytes};
+use support::stream::Stream;
+use tokio_proto::io::{FrameTooLarge, Transport};
+use tokio_proto::io::codec::delimited::Builder;
+
+fn to_vec(frame: Option<Bytes>) -> Option<Vec<u8>> {
+    frame.map(|frame| {
+        let mut buf = frame.buf();
+        let mut ret = vec![];
+
+        while let Some(byte) = buf.read_byte() {
+            ret.push(byte);
+        }
+
+        ret
+    })
+}
+
+fn assert_frame_too_large(err: std_io::Error, len: usize, max: usize) {
+    assert_eq!(std_io::ErrorKind::InvalidData, err.kind());
+
+    let err = err.get_ref().and_then(|e| e.downcast_ref::<FrameTooLarge>()).unwrap();
+    assert_eq!(FrameTooLarge { len: len, max: max }, *err);
+}
+
+#[test]
+fn test_read_lines() {
+    let stream = Stream::new();
+    let mut transport = Builder::lines().frame_strings(stream.clone());
+
+    stream.feed(b"hello\nwor");
+    assert_eq!(Some("hello".to_string()), transport.read().unwrap());
+    assert_eq!(None, transport.read().unwrap());
+
+    stream.feed(b"ld\n\n");
+    assert_eq!(Some("world".to_string()), transport.read().unwrap());
+    assert_eq!(Some("".to_string()), transport.read().unwrap());
+    assert_eq!(None, transport.read().unwrap());
+}
+
+#[test]
+fn test_read_crlf_split_delimiter() {
+    let stream = Stream::new();
+    let mut transport = Builder::crlf().frame(stream.clone());
+
+    stream.feed(b"a\rb\r");
+    assert_eq!(None, to_vec(transport.read().unwrap()));
+
+    stream.feed(b"\nc\r\n");
+    assert_eq!(Some(b"a\rb".to_vec()), to_vec(transport.read().unwrap()));
+    assert_eq!(Some(b"c".to_vec()), to_vec(transport.read().unwrap()));
+}
+
+#[test]
+fn test_read_nul() {
+    let stream = Stream::new();
+    let mut transport = Builder::nul().frame(stream.clone());
+
+    stream.feed(b"a\0bc\0");
+    assert_eq!(Some(b"a".to_vec()), to_vec(transport.read().unwrap()));
+    assert_eq!(Some(b"bc".to_vec()), to_vec(transport.read().unwrap()));
+}
+
+#[test]
+fn test_read_invalid_utf8() {
+    let stream = Stream::new();
+    let mut transport = Builder::lines().frame_strings(stream.clone());
+
+    stream.feed(b"\xff\nok\n");
+
+    let err = transport.read().unwrap_err();
+    assert_eq!(std_io::ErrorKind::InvalidData, err.kind());
+
+    assert_eq!(Some("ok".to_string()), transport.read().unwrap());
+}
+
+#[test]
+fn test_read_last_frame_at_eof() {
+    let stream = Stream::new();
+    let mut transport = Builder::lines().frame_strings(stream.clone());
+
+    stream.feed(b"a\nb");
+    stream.close();
+
+    assert_eq!(Some("a".to_string()), transport.read().unwrap());
+    assert_eq!(Some("b".to_string()), transport.read().unwrap());
+    assert_eq!(None, transport.read().unwrap());
+}
+
+#[test]
+fn test_read_too_long_discards_until_delimiter() {
+    let stream = Stream::new();
+    let mut transport = Builder::lines().max_length(4).frame_strings(stream.clone());
+
+    stream.feed(b"abcdef");
+    assert_frame_too_large(transport.read().unwrap_err(), 6, 4);
+
+    // The rest of the long frame is discarded
+    stream.feed(b"gh");
+    assert_eq!(None, transport.read().unwrap());
+
+    stream.feed(b"i\nok\n");
+    assert_eq!(Some("ok".to_string()), transport.read().unwrap());
+}
+
+#[test]
+fn test_read_too_long_with_delimiter() {
+    let stream = Stream::new();
+    let mut transport = Builder::lines().max_length(2).frame_strings(stream.clone());
+
+    stream.feed(b"abc\nok\n");
+    assert_frame_too_large(transport.read().unwrap_err(), 3, 2);
+    assert_eq!(Some("ok".to_string()), transport.read().unwrap());
+}
+
+#[test]
+fn test_write() {
+    let stream = Stream::new();
+    let mut transport = Builder::crlf().frame_strings(stream.clone());
+
+    transport.write("hello".to_string()).unwrap();
+    transport.write("world".to_string()).unwrap();
+
+    assert_eq!(Some(()), transport.flush().unwrap());
+    assert_eq!(b"hello\r\nworld\r\n".to_vec(), stream.written());
+}
+
+#[test]
+fn test_write_containing_delimiter() {
+    let stream = Stream::new();
+    let mut transport = Builder::lines().frame(stream.clone());
+
+    let err = transport.write(b"a\nb".to_vec()).unwrap_err();
+    assert_eq!(std_io::ErrorKind::InvalidInput, err.kind());
+}
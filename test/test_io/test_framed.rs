@@ -0,0 +1,337 @@
+use std::io as std_io;
+
+use bytes::{Buf, BlockBuf, MutBuf};
+use support::stream::Stream;
+use tokio_proto::io::{self, Framed, FlushPolicy, FrameTooLarge, Parse, Parts, Readiness, Serialize, Transport};
+use tokio_proto::io::{SimpleParse, SimpleSerialize, TryWrite};
+
+// Parses newline terminated frames
+struct Lines;
+
+impl SimpleParse for Lines {
+    type Out = Vec<u8>;
+
+    fn parse(&mut self, buf: &mut BlockBuf) -> Option<Vec<u8>> {
+        buf.compact();
+
+        let pos = match buf.bytes().and_then(|bytes| bytes.iter().position(|b| *b == b'\n')) {
+            Some(pos) => pos,
+            None => return None,
+        };
+
+        let line = buf.bytes().unwrap()[..pos].to_vec();
+        buf.drop(pos + 1);
+
+        Some(line)
+    }
+}
+
+// Parses frames prefixed by a one byte length, empty frames are invalid
+struct Prefixed;
+
+impl Parse for Prefixed {
+    type Out = Vec<u8>;
+
+    fn parse(&mut self, buf: &mut BlockBuf) -> std_io::Result<Option<Vec<u8>>> {
+        let len = match self.frame_len(buf) {
+            Some(1) => return Err(std_io::Error::new(std_io::ErrorKind::InvalidData, "empty frame")),
+            Some(len) if buf.len() >= len => len,
+            _ => return Ok(None),
+        };
+
+        buf.compact();
+
+        let frame = buf.bytes().unwrap()[1..len].to_vec();
+        buf.drop(len);
+
+        Ok(Some(frame))
+    }
+
+    fn frame_len(&self, buf: &BlockBuf) -> Option<usize> {
+        let mut cursor = buf.buf();
+
+        if cursor.remaining() == 0 {
+            return None;
+        }
+
+        Some(1 + cursor.read_byte().unwrap() as usize)
+    }
+}
+
+// Writes frames verbatim
+struct Raw;
+
+impl SimpleSerialize for Raw {
+    type In = Vec<u8>;
+
+    fn serialize(&mut self, msg: Vec<u8>, buf: &mut BlockBuf) {
+        buf.write_slice(&msg);
+    }
+}
+
+// Writes frames prefixed by a one byte length, empty frames are invalid
+struct PrefixedWriter;
+
+impl Serialize for PrefixedWriter {
+    type In = Vec<u8>;
+
+    fn serialize(&mut self, msg: Vec<u8>, buf: &mut BlockBuf) -> std_io::Result<()> {
+        if msg.is_empty() || msg.len() > 255 {
+            return Err(std_io::Error::new(std_io::ErrorKind::InvalidInput, "invalid frame length"));
+        }
+
+        buf.write_slice(&[msg.len() as u8]);
+        buf.write_slice(&msg);
+        Ok(())
+    }
+}
+
+fn framed(stream: &Stream) -> Framed<Stream, Lines, Raw> {
+    io::Stream::frame(stream.clone(), Lines, Raw)
+}
+
+#[test]
+fn test_write_high_water_mark() {
+    let stream = Stream::new();
+    stream.set_write_capacity(Some(0));
+
+    let mut transport = framed(&stream);
+    transport.set_write_water_marks(4, 8);
+
+    transport.write(b"abc".to_vec()).unwrap();
+    assert!(transport.is_writable());
+
+    transport.write(b"defgh".to_vec()).unwrap();
+    assert!(!transport.is_writable());
+
+    // Writing past the high water mark is an error
+    assert!(transport.write(b"i".to_vec()).is_err());
+
+    // Draining to above the low water mark keeps the transport blocked
+    stream.set_write_capacity(Some(3));
+    assert_eq!(None, transport.flush().unwrap());
+    assert!(!transport.is_writable());
+
+    // Draining to the low water mark unblocks it
+    stream.set_write_capacity(Some(1));
+    assert_eq!(None, transport.flush().unwrap());
+    assert!(transport.is_writable());
+
+    stream.set_write_capacity(None);
+    assert_eq!(Some(()), transport.flush().unwrap());
+    assert_eq!(b"abcdefgh".to_vec(), stream.written());
+}
+
+#[test]
+fn test_max_buffered() {
+    let stream = Stream::new();
+    let mut transport = framed(&stream);
+    transport.set_max_buffered(4);
+
+    stream.feed(b"ab\ncdefg");
+
+    assert_eq!(Some(b"ab".to_vec()), transport.read().unwrap());
+    assert_frame_too_large(transport.read().unwrap_err(), 5, 4);
+}
+
+#[test]
+fn test_max_buffered_uses_reported_frame_len() {
+    let stream = Stream::new();
+    let mut transport = io::Stream::frame(stream.clone(), Prefixed, Raw);
+    transport.set_max_buffered(10);
+
+    stream.feed(&[3, b'a', b'b', b'c', 100, b'd']);
+
+    assert_eq!(Some(b"abc".to_vec()), transport.read().unwrap());
+
+    // Rejected before the frame is buffered
+    assert_frame_too_large(transport.read().unwrap_err(), 101, 10);
+}
+
+fn assert_frame_too_large(err: std_io::Error, len: usize, max: usize) {
+    assert_eq!(std_io::ErrorKind::InvalidData, err.kind());
+
+    let err = err.get_ref().and_then(|e| e.downcast_ref::<FrameTooLarge>()).unwrap();
+    assert_eq!(FrameTooLarge { len: len, max: max }, *err);
+}
+
+#[test]
+fn test_parse_error() {
+    let stream = Stream::new();
+    let mut transport = io::Stream::frame(stream.clone(), Prefixed, Raw);
+
+    stream.feed(&[1, b'a', 0]);
+
+    assert_eq!(Some(b"a".to_vec()), transport.read().unwrap());
+
+    let err = transport.read().unwrap_err();
+    assert_eq!(std_io::ErrorKind::InvalidData, err.kind());
+    assert_eq!("empty frame", err.to_string());
+}
+
+#[test]
+fn test_serialize_error() {
+    let stream = Stream::new();
+    let mut transport = io::Stream::frame(stream.clone(), Prefixed, PrefixedWriter);
+
+    transport.write(b"ab".to_vec()).unwrap();
+
+    let err = transport.write(vec![]).unwrap_err();
+    assert_eq!(std_io::ErrorKind::InvalidInput, err.kind());
+
+    assert_eq!(Some(()), transport.flush().unwrap());
+    assert_eq!(vec![2, b'a', b'b'], stream.written());
+}
+
+#[test]
+fn test_partial_frame_at_eof() {
+    let stream = Stream::new();
+    let mut transport = framed(&stream);
+
+    stream.feed(b"ab\ncd");
+    stream.close();
+
+    assert_eq!(Some(b"ab".to_vec()), transport.read().unwrap());
+
+    let err = transport.read().unwrap_err();
+    assert_eq!(std_io::ErrorKind::UnexpectedEof, err.kind());
+}
+
+// Yields the remaining bytes, one frame per byte, once the stream closed
+struct Trailing;
+
+impl SimpleParse for Trailing {
+    type Out = Vec<u8>;
+
+    fn parse(&mut self, _: &mut BlockBuf) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn done(&mut self, buf: &mut BlockBuf) -> Option<Vec<u8>> {
+        if buf.is_empty() {
+            return None;
+        }
+
+        let byte = buf.buf().read_byte().unwrap();
+        buf.drop(1);
+
+        Some(vec![byte])
+    }
+}
+
+#[test]
+fn test_done_yields_multiple_frames() {
+    let stream = Stream::new();
+    let mut transport = io::Stream::frame(stream.clone(), Trailing, Raw);
+
+    stream.feed(b"ab");
+    assert_eq!(None, transport.read().unwrap());
+
+    stream.close();
+
+    assert_eq!(Some(b"a".to_vec()), transport.read().unwrap());
+    assert!(transport.is_readable());
+    assert_eq!(Some(b"b".to_vec()), transport.read().unwrap());
+    assert_eq!(None, transport.read().unwrap());
+}
+
+fn framed_with_blocks(stream: &Stream, block_size: usize) -> Framed<Stream, Lines, Raw> {
+    Framed::new(stream.clone(), Lines, Raw, BlockBuf::default(), BlockBuf::new(16, block_size))
+}
+
+#[test]
+fn test_flush_policy_block_full() {
+    let stream = Stream::new();
+    let mut transport = framed_with_blocks(&stream, 4);
+
+    // The first block is full and written right away
+    assert_eq!(None, transport.write(b"abcdef".to_vec()).unwrap());
+    assert_eq!(b"abcd".to_vec(), stream.written());
+
+    assert_eq!(Some(()), transport.flush().unwrap());
+    assert_eq!(b"ef".to_vec(), stream.written());
+}
+
+#[test]
+fn test_flush_policy_always() {
+    let stream = Stream::new();
+    let mut transport = framed_with_blocks(&stream, 4);
+    transport.set_flush_policy(FlushPolicy::Always);
+
+    assert_eq!(Some(()), transport.write(b"ab".to_vec()).unwrap());
+    assert_eq!(b"ab".to_vec(), stream.written());
+}
+
+#[test]
+fn test_flush_policy_manual() {
+    let stream = Stream::new();
+    let mut transport = framed_with_blocks(&stream, 4);
+    transport.set_flush_policy(FlushPolicy::Manual);
+
+    assert_eq!(None, transport.write(b"abcdef".to_vec()).unwrap());
+    assert_eq!(0, stream.writes());
+
+    assert_eq!(Some(()), transport.flush().unwrap());
+    assert_eq!(b"abcdef".to_vec(), stream.written());
+}
+
+#[test]
+fn test_flush_writes_all_blocks() {
+    let stream = Stream::new();
+    let mut transport = framed_with_blocks(&stream, 4);
+    transport.set_flush_policy(FlushPolicy::Manual);
+
+    for _ in 0..5 {
+        transport.write(b"ab".to_vec()).unwrap();
+    }
+
+    // Blocks are written across partial writes
+    stream.set_write_capacity(Some(3));
+    assert_eq!(None, transport.flush().unwrap());
+    assert_eq!(b"aba".to_vec(), stream.written());
+
+    stream.set_write_capacity(None);
+    assert_eq!(Some(()), transport.flush().unwrap());
+    assert_eq!(b"bababab".to_vec(), stream.written());
+}
+
+#[test]
+fn test_try_write_vectored_falls_back_to_try_write() {
+    let mut stream = Stream::new();
+
+    assert_eq!(Some(2), stream.try_write_vectored(&[&b""[..], &b"ab"[..], &b"cd"[..]]).unwrap());
+    assert_eq!(b"ab".to_vec(), stream.written());
+    assert_eq!(1, stream.writes());
+
+    assert_eq!(Some(0), stream.try_write_vectored(&[&b""[..]]).unwrap());
+    assert_eq!(1, stream.writes());
+}
+
+#[test]
+fn test_switch_parser_keeps_buffered_bytes() {
+    let stream = Stream::new();
+    let mut transport = framed(&stream);
+    transport.set_flush_policy(FlushPolicy::Manual);
+
+    stream.feed(&[b'h', b'i', b'\n', 2, b'a', b'b']);
+    assert_eq!(Some(b"hi".to_vec()), transport.read().unwrap());
+
+    transport.write(b"ok\n".to_vec()).unwrap();
+
+    let parts = transport.into_parts();
+    let mut transport = Framed::from_parts(Parts {
+        upstream: parts.upstream,
+        parse: Prefixed,
+        serialize: PrefixedWriter,
+        rd: parts.rd,
+        wr: parts.wr,
+    });
+
+    // The bytes buffered by the previous parser are parsed by the new one
+    assert!(transport.is_readable());
+    assert_eq!(Some(b"ab".to_vec()), transport.read().unwrap());
+
+    transport.write(b"c".to_vec()).unwrap();
+    assert_eq!(Some(()), transport.flush().unwrap());
+    assert_eq!(vec![b'o', b'k', b'\n', 1, b'c'], transport.get_ref().written());
+}
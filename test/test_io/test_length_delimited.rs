@@ -0,0 +1,185 @@
+use std::io as std_io;
+
+use bytes::{Buf, Bytes};
+use support::stream::Stream;
+use tokio_proto::io::{FrameTooLarge, Transport};
+use tokio_proto::io::codec::length_delimited::{Builder, Endianness, LengthField};
+
+fn to_vec(frame: Option<Bytes>) -> Option<Vec<u8>> {
+    frame.map(|frame| {
+        let mut buf = frame.buf();
+        let mut ret = vec![];
+
+        while let Some(byte) = buf.read_byte() {
+            ret.push(byte);
+        }
+
+        ret
+    })
+}
+
+#[test]
+fn test_read_default() {
+    let stream = Stream::new();
+    let mut transport = Builder::new().frame(stream.clone());
+
+    stream.feed(&[0, 0, 0, 3, b'a', b'b']);
+    assert_eq!(None, to_vec(transport.read().unwrap()));
+
+    stream.feed(&[b'c', 0, 0, 0, 0, 0, 0, 0, 1, b'd']);
+    assert_eq!(Some(b"abc".to_vec()), to_vec(transport.read().unwrap()));
+    assert_eq!(Some(vec![]), to_vec(transport.read().unwrap()));
+    assert_eq!(Some(b"d".to_vec()), to_vec(transport.read().unwrap()));
+    assert_eq!(None, to_vec(transport.read().unwrap()));
+}
+
+#[test]
+fn test_read_little_endian_u16() {
+    let stream = Stream::new();
+    let mut transport = Builder::new()
+        .length_field(LengthField::U16)
+        .endianness(Endianness::Little)
+        .frame(stream.clone());
+
+    stream.feed(&[2, 0, b'a', b'b']);
+    assert_eq!(Some(b"ab".to_vec()), to_vec(transport.read().unwrap()));
+}
+
+#[test]
+fn test_read_varint() {
+    let stream = Stream::new();
+    let mut transport = Builder::new()
+        .length_field(LengthField::Varint)
+        .frame(stream.clone());
+
+    let mut bytes = vec![0xac, 0x02];
+    bytes.extend(vec![b'x'; 300]);
+
+    stream.feed(&bytes[..1]);
+    assert_eq!(None, to_vec(transport.read().unwrap()));
+
+    stream.feed(&bytes[1..]);
+    assert_eq!(Some(vec![b'x'; 300]), to_vec(transport.read().unwrap()));
+}
+
+#[test]
+fn test_read_varint_overflow() {
+    let stream = Stream::new();
+    let mut transport = Builder::new()
+        .length_field(LengthField::Varint)
+        .frame(stream.clone());
+
+    // The tenth byte holds more than the most significant bit of a u64
+    stream.feed(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]);
+
+    let err = transport.read().unwrap_err();
+    assert_eq!(std_io::ErrorKind::InvalidData, err.kind());
+}
+
+#[test]
+fn test_read_length_overflow() {
+    let builder = Builder::new()
+        .length_field(LengthField::U64)
+        .length_adjustment(4);
+
+    // Too large once adjusted, rather than wrapping around
+    for len in &[[0x80, 0, 0, 0, 0, 0, 0, 0], [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe]] {
+        let stream = Stream::new();
+        let mut transport = builder.frame(stream.clone());
+
+        stream.feed(len);
+        stream.feed(b"ab");
+
+        let err = transport.read().unwrap_err();
+        assert_eq!(std_io::ErrorKind::InvalidData, err.kind());
+    }
+}
+
+#[test]
+fn test_read_offset_and_adjustment() {
+    let stream = Stream::new();
+
+    // A one byte type, then a length including the whole header
+    let mut transport = Builder::new()
+        .length_field(LengthField::U8)
+        .length_field_offset(1)
+        .length_adjustment(-2)
+        .strip_header(false)
+        .frame(stream.clone());
+
+    stream.feed(&[7, 4, b'a', b'b']);
+    assert_eq!(Some(vec![7, 4, b'a', b'b']), to_vec(transport.read().unwrap()));
+}
+
+#[test]
+fn test_read_frame_too_large() {
+    let stream = Stream::new();
+    let mut transport = Builder::new()
+        .max_frame_length(4)
+        .frame(stream.clone());
+
+    stream.feed(&[0, 0, 0, 5]);
+
+    let err = transport.read().unwrap_err();
+    assert_eq!(std_io::ErrorKind::InvalidData, err.kind());
+
+    let err = err.get_ref().and_then(|e| e.downcast_ref::<FrameTooLarge>()).unwrap();
+    assert_eq!(FrameTooLarge { len: 5, max: 4 }, *err);
+}
+
+#[test]
+fn test_read_negative_length() {
+    let stream = Stream::new();
+    let mut transport = Builder::new()
+        .length_adjustment(-4)
+        .frame(stream.clone());
+
+    stream.feed(&[0, 0, 0, 2]);
+
+    let err = transport.read().unwrap_err();
+    assert_eq!(std_io::ErrorKind::InvalidData, err.kind());
+}
+
+#[test]
+fn test_write() {
+    let stream = Stream::new();
+    let mut transport = Builder::new()
+        .length_field(LengthField::U16)
+        .frame(stream.clone());
+
+    transport.write(b"abc".to_vec()).unwrap();
+    transport.write(vec![]).unwrap();
+
+    assert_eq!(Some(()), transport.flush().unwrap());
+    assert_eq!(vec![0, 3, b'a', b'b', b'c', 0, 0], stream.written());
+}
+
+#[test]
+fn test_write_offset_and_varint() {
+    let stream = Stream::new();
+    let mut transport = Builder::new()
+        .length_field(LengthField::Varint)
+        .length_field_offset(1)
+        .frame(stream.clone());
+
+    let mut msg = vec![7];
+    msg.extend(vec![b'x'; 300]);
+
+    transport.write(msg).unwrap();
+    assert_eq!(Some(()), transport.flush().unwrap());
+
+    let written = stream.written();
+    assert_eq!(vec![7, 0xac, 0x02], &written[..3]);
+    assert_eq!(303, written.len());
+}
+
+#[test]
+fn test_write_length_does_not_fit() {
+    let stream = Stream::new();
+    let mut transport = Builder::new()
+        .length_field(LengthField::U8)
+        .frame(stream.clone());
+
+    let err = transport.write(vec![0; 256]).unwrap_err();
+    assert_eq!(std_io::ErrorKind::InvalidInput, err.kind());
+}
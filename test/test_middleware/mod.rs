@@ -0,0 +1,9 @@
+mod test_buffer;
+mod test_cache;
+mod test_circuit_breaker;
+mod test_coalesce;
+mod test_hedge;
+mod test_metrics;
+mod test_mirror;
+mod test_router;
+mod test_thread_pool;
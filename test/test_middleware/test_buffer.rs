@@ -0,0 +1,57 @@
+use std::io;
+use std::sync::mpsc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use futures::{finished, oneshot, Future};
+use tokio_proto::{self, Service};
+use tokio_proto::middleware::buffer::Buffer;
+use tokio_core::Loop;
+
+#[test]
+fn test_calls_shared_service() {
+    let (tx, rx) = oneshot();
+    let (tx2, rx2) = mpsc::channel();
+    let t = thread::spawn(move || {
+        let mut lp = Loop::new().unwrap();
+        tx2.send(lp.handle()).unwrap();
+        lp.run(rx)
+    });
+
+    let handle = rx2.recv().unwrap();
+
+    // A counter shared by all the handles
+    let buffer = Buffer::new(handle, || {
+        let count = AtomicUsize::new(0);
+
+        tokio_proto::simple_service(move |_: ()| {
+            finished::<usize, io::Error>(count.fetch_add(1, Ordering::SeqCst))
+        })
+    }, 16);
+    let other = buffer.clone();
+
+    assert_eq!(0, buffer.call(()).wait().unwrap());
+    assert_eq!(1, other.call(()).wait().unwrap());
+    assert_eq!(2, buffer.call(()).wait().unwrap());
+
+    tx.complete(());
+    t.join().unwrap().unwrap();
+}
+
+#[test]
+fn test_waits_for_slot_when_full() {
+    let mut lp = Loop::new().unwrap();
+
+    let buffer = Buffer::new(lp.handle(), || {
+        tokio_proto::simple_service(|req: u32| finished::<u32, io::Error>(req))
+    }, 1);
+
+    // The loop is not running yet, so requests are not picked up
+    let one = buffer.call(1);
+    let two = buffer.call(2);
+    assert_eq!(1, buffer.len());
+
+    // The second call is sent once the task picked up the first one
+    assert_eq!((1, 2), lp.run(one.join(two)).unwrap());
+    assert!(buffer.is_empty());
+}
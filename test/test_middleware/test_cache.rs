@@ -0,0 +1,128 @@
+use std::io;
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use futures::{done, oneshot, Done, Future};
+use support::{self, millis};
+use tokio_proto::{self, Service};
+use tokio_proto::middleware::cache::{Builder, CacheError};
+use tokio_core::{Loop, LoopHandle};
+
+#[test]
+fn test_serves_repeated_requests_from_cache() {
+    run(|handle| {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cache = Builder::new(16).build(handle, counting(calls.clone()), |req: &u32| *req);
+
+        assert_eq!(1, cache.call(1).wait().unwrap());
+        assert_eq!(1, cache.call(1).wait().unwrap());
+        assert_eq!(2, cache.call(2).wait().unwrap());
+
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+        assert_eq!(2, cache.len());
+    });
+}
+
+#[test]
+fn test_entries_expire() {
+    run(|handle| {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cache = Builder::new(16)
+            .ttl(millis(20))
+            .build(handle, counting(calls.clone()), |req: &u32| *req);
+
+        assert_eq!(1, cache.call(1).wait().unwrap());
+
+        support::sleep_ms(100);
+
+        // Removed by the sweeper, without being looked up
+        assert!(cache.is_empty());
+
+        assert_eq!(1, cache.call(1).wait().unwrap());
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    });
+}
+
+#[test]
+fn test_evicts_least_recently_used() {
+    run(|handle| {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cache = Builder::new(2).build(handle, counting(calls.clone()), |req: &u32| *req);
+
+        cache.call(1).wait().unwrap();
+        cache.call(2).wait().unwrap();
+
+        // Touch 1 so that 2 is the least recently used entry
+        cache.call(1).wait().unwrap();
+        cache.call(3).wait().unwrap();
+
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+
+        cache.call(1).wait().unwrap();
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+
+        cache.call(2).wait().unwrap();
+        assert_eq!(4, calls.load(Ordering::SeqCst));
+    });
+}
+
+#[test]
+fn test_negative_caching() {
+    run(|handle| {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls2 = calls.clone();
+
+        let service = tokio_proto::simple_service(move |_: u32| {
+            calls2.fetch_add(1, Ordering::SeqCst);
+            done::<u32, io::Error>(Err(io::Error::new(io::ErrorKind::Other, "nope")))
+        });
+
+        let cache = Builder::new(16)
+            .negative_ttl(millis(1_000))
+            .build(handle, service, |req: &u32| *req);
+
+        assert_eq!("nope", cache.call(1).wait().unwrap_err().to_string());
+
+        let err = cache.call(1).wait().unwrap_err();
+        assert_eq!(CacheError::Negative.to_string(), err.to_string());
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+    });
+}
+
+// Echo service counting its calls
+#[derive(Clone)]
+struct Counting {
+    calls: Arc<AtomicUsize>,
+}
+
+impl Service for Counting {
+    type Req = u32;
+    type Resp = u32;
+    type Error = io::Error;
+    type Fut = Done<u32, io::Error>;
+
+    fn call(&self, req: u32) -> Self::Fut {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        done(Ok(req))
+    }
+}
+
+fn counting(calls: Arc<AtomicUsize>) -> Counting {
+    Counting { calls: calls }
+}
+
+fn run<F>(f: F) where F: FnOnce(LoopHandle) {
+    let (tx, rx) = oneshot();
+    let (tx2, rx2) = mpsc::channel();
+    let t = thread::spawn(move || {
+        let mut lp = Loop::new().unwrap();
+        tx2.send(lp.handle()).unwrap();
+        lp.run(rx)
+    });
+
+    f(rx2.recv().unwrap());
+
+    tx.complete(());
+    t.join().unwrap().unwrap();
+}
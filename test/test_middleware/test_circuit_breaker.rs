@@ -0,0 +1,158 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use futures::{self, done, BoxFuture, Future};
+use support::{self, millis};
+use tokio_proto::{self, Service};
+use tokio_proto::middleware::circuit_breaker::{Builder, State};
+
+#[test]
+fn test_passes_through_while_closed() {
+    let breaker = Builder::new().build(tokio_proto::simple_service(|req: u32| {
+        done::<u32, io::Error>(Ok(req + 1))
+    }));
+
+    assert_eq!(2, breaker.call(1).wait().unwrap());
+    assert_eq!(State::Closed, breaker.state());
+}
+
+#[test]
+fn test_opens_and_rejects_when_failing() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let calls2 = calls.clone();
+
+    let breaker = Builder::new()
+        .min_requests(3)
+        .error_rate(0.5)
+        .cooldown(millis(1_000))
+        .build(tokio_proto::simple_service(move |_: u32| {
+            calls2.fetch_add(1, Ordering::SeqCst);
+            done::<u32, io::Error>(Err(io::Error::new(io::ErrorKind::Other, "nope")))
+        }));
+
+    for _ in 0..3 {
+        assert!(breaker.call(1).wait().is_err());
+    }
+
+    assert_eq!(State::Open, breaker.state());
+    assert_eq!(3, calls.load(Ordering::SeqCst));
+
+    // Rejected without calling the inner service
+    assert!(breaker.call(1).wait().is_err());
+    assert_eq!(3, calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_closes_after_successful_probe() {
+    let fail = Arc::new(AtomicBool::new(true));
+    let fail2 = fail.clone();
+
+    let breaker = Builder::new()
+        .min_requests(1)
+        .cooldown(millis(20))
+        .build(tokio_proto::simple_service(move |req: u32| {
+            if fail2.load(Ordering::SeqCst) {
+                done(Err(io::Error::new(io::ErrorKind::Other, "nope")))
+            } else {
+                done(Ok(req))
+            }
+        }));
+
+    assert!(breaker.call(1).wait().is_err());
+    assert_eq!(State::Open, breaker.state());
+
+    support::sleep_ms(40);
+    assert_eq!(State::HalfOpen, breaker.state());
+
+    fail.store(false, Ordering::SeqCst);
+    assert_eq!(1, breaker.call(1).wait().unwrap());
+    assert_eq!(State::Closed, breaker.state());
+}
+
+#[test]
+fn test_reopens_after_failed_probe() {
+    let breaker = Builder::new()
+        .min_requests(1)
+        .cooldown(millis(20))
+        .build(tokio_proto::simple_service(|_: u32| {
+            done::<u32, io::Error>(Err(io::Error::new(io::ErrorKind::Other, "nope")))
+        }));
+
+    assert!(breaker.call(1).wait().is_err());
+
+    support::sleep_ms(40);
+
+    assert!(breaker.call(1).wait().is_err());
+    assert_eq!(State::Open, breaker.state());
+}
+
+#[test]
+fn test_dropped_probe_reopens_circuit() {
+    let hang = Arc::new(AtomicBool::new(false));
+    let hang2 = hang.clone();
+
+    let breaker = Builder::new()
+        .min_requests(1)
+        .cooldown(millis(20))
+        .build(tokio_proto::simple_service(move |_: u32| -> BoxFuture<u32, io::Error> {
+            if hang2.load(Ordering::SeqCst) {
+                futures::empty().boxed()
+            } else {
+                futures::failed(io::Error::new(io::ErrorKind::Other, "nope")).boxed()
+            }
+        }));
+
+    assert!(breaker.call(1).wait().is_err());
+
+    support::sleep_ms(40);
+    hang.store(true, Ordering::SeqCst);
+
+    // The probe is dropped before completing
+    drop(breaker.call(1));
+    assert_eq!(State::Open, breaker.state());
+
+    // Once cooled down again, a new probe is let through
+    support::sleep_ms(40);
+    assert_eq!(State::HalfOpen, breaker.state());
+}
+
+#[test]
+fn test_only_probe_result_closes_circuit() {
+    let (tx, rx) = futures::oneshot();
+    let slow = Arc::new(Mutex::new(Some(rx)));
+
+    let breaker = Builder::new()
+        .min_requests(1)
+        .cooldown(millis(20))
+        .build(tokio_proto::simple_service(move |req: u32| -> BoxFuture<u32, io::Error> {
+            match req {
+                0 => {
+                    let rx = slow.lock().unwrap().take().unwrap();
+                    rx.map_err(|_| io::Error::new(io::ErrorKind::Other, "canceled")).boxed()
+                }
+                1 => futures::empty().boxed(),
+                _ => futures::failed(io::Error::new(io::ErrorKind::Other, "nope")).boxed(),
+            }
+        }));
+
+    // Dispatched while the circuit is closed
+    let slow = breaker.call(0);
+
+    assert!(breaker.call(2).wait().is_err());
+    assert_eq!(State::Open, breaker.state());
+
+    support::sleep_ms(40);
+
+    // The probe never completes
+    let _probe = breaker.call(1);
+
+    // The call dispatched before the circuit opened does not count as the
+    // probe result
+    tx.complete(5);
+    assert_eq!(5, slow.wait().unwrap());
+    assert_eq!(State::HalfOpen, breaker.state());
+
+    let err = breaker.call(3).wait().unwrap_err();
+    assert_eq!("circuit breaker open", err.to_string());
+}
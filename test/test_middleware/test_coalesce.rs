@@ -0,0 +1,83 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::{oneshot, Complete, Future};
+use tokio_proto::{self, Service};
+use tokio_proto::middleware::coalesce::{Coalesce, CoalesceError};
+
+#[test]
+fn test_concurrent_identical_requests_share_call() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let pending: Arc<Mutex<Vec<Complete<Result<String, io::Error>>>>> = Arc::new(Mutex::new(vec![]));
+
+    let calls2 = calls.clone();
+    let pending2 = pending.clone();
+
+    let service = tokio_proto::simple_service(move |_: &'static str| {
+        calls2.fetch_add(1, Ordering::SeqCst);
+
+        let (c, rx) = oneshot();
+        pending2.lock().unwrap().push(c);
+        rx.then(|r| r.unwrap())
+    });
+
+    let service = Coalesce::new(service, |req: &&'static str| *req);
+
+    let one = service.call("a");
+    let two = service.call("a");
+    let other = service.call("b");
+
+    assert_eq!(2, calls.load(Ordering::SeqCst));
+    assert_eq!(2, service.in_flight());
+
+    let mut pending = pending.lock().unwrap();
+    pending.remove(0).complete(Ok("resp-a".to_string()));
+    pending.remove(0).complete(Ok("resp-b".to_string()));
+
+    assert_eq!("resp-a", one.wait().unwrap());
+    assert_eq!("resp-a", two.wait().unwrap());
+    assert_eq!("resp-b", other.wait().unwrap());
+
+    assert_eq!(0, service.in_flight());
+}
+
+#[test]
+fn test_waiters_receive_failed_error() {
+    let pending = Arc::new(Mutex::new(None));
+    let pending2 = pending.clone();
+
+    let service = tokio_proto::simple_service(move |_: u32| {
+        let (c, rx) = oneshot::<Result<String, io::Error>>();
+        *pending2.lock().unwrap() = Some(c);
+        rx.then(|r| r.unwrap())
+    });
+
+    let service = Coalesce::new(service, |req: &u32| *req);
+
+    let one = service.call(1);
+    let two = service.call(1);
+
+    let c = pending.lock().unwrap().take().unwrap();
+    c.complete(Err(io::Error::new(io::ErrorKind::Other, "nope")));
+
+    assert_eq!("nope", one.wait().unwrap_err().to_string());
+    assert_eq!(CoalesceError::Failed.to_string(), two.wait().unwrap_err().to_string());
+}
+
+#[test]
+fn test_sequential_requests_are_not_coalesced() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let calls2 = calls.clone();
+
+    let service = tokio_proto::simple_service(move |req: u32| {
+        calls2.fetch_add(1, Ordering::SeqCst);
+        ::futures::finished::<u32, io::Error>(req)
+    });
+
+    let service = Coalesce::new(service, |req: &u32| *req);
+
+    assert_eq!(1, service.call(1).wait().unwrap());
+    assert_eq!(1, service.call(1).wait().unwrap());
+    assert_eq!(2, calls.load(Ordering::SeqCst));
+}
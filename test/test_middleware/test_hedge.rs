@@ -0,0 +1,87 @@
+use std::io;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use futures::{failed, finished, oneshot, BoxFuture, Complete, Future};
+use tokio_proto::{self, Service};
+use tokio_proto::middleware::hedge::Builder;
+use tokio_core::Loop;
+
+// Never completes the first request for `1`, answers everything else
+// immediately.
+#[derive(Clone)]
+struct ParkFirst {
+    parked: Arc<Mutex<Option<Complete<u32>>>>,
+}
+
+impl Service for ParkFirst {
+    type Req = u32;
+    type Resp = u32;
+    type Error = io::Error;
+    type Fut = BoxFuture<u32, io::Error>;
+
+    fn call(&self, req: u32) -> Self::Fut {
+        let mut parked = self.parked.lock().unwrap();
+
+        if req == 1 && parked.is_none() {
+            let (c, rx) = oneshot();
+            *parked = Some(c);
+
+            return rx.map_err(|_| io::Error::new(io::ErrorKind::Other, "canceled")).boxed();
+        }
+
+        finished(req).boxed()
+    }
+}
+
+#[test]
+fn test_hedges_slow_request() {
+    let (tx, rx) = oneshot();
+    let (tx2, rx2) = mpsc::channel();
+    let t = thread::spawn(move || {
+        let mut lp = Loop::new().unwrap();
+        tx2.send(lp.handle()).unwrap();
+        lp.run(rx)
+    });
+
+    let handle = rx2.recv().unwrap();
+
+    let service = ParkFirst { parked: Arc::new(Mutex::new(None)) };
+    let hedge = Builder::new()
+        .min_samples(5)
+        .percentile(0.5)
+        .budget(1.0)
+        .build(handle, service);
+
+    // Warm up the latency histogram
+    for _ in 0..5 {
+        assert_eq!(0, hedge.call(0).wait().unwrap());
+    }
+
+    assert_eq!(0, hedge.hedged());
+    assert_eq!(5, hedge.latency().count());
+
+    // The first copy never completes, the hedged copy does
+    assert_eq!(1, hedge.call(1).wait().unwrap());
+    assert_eq!(1, hedge.hedged());
+
+    // Only the first copy is measured
+    assert_eq!(5, hedge.latency().count());
+
+    tx.complete(());
+    t.join().unwrap().unwrap();
+}
+
+#[test]
+fn test_records_failed_calls() {
+    let lp = Loop::new().unwrap();
+
+    let service = tokio_proto::simple_service(|_: u32| {
+        failed::<u32, io::Error>(io::Error::new(io::ErrorKind::Other, "nope"))
+    });
+
+    let hedge = Builder::new().build(lp.handle(), service);
+
+    assert!(hedge.call(0).wait().is_err());
+    assert_eq!(1, hedge.latency().count());
+}
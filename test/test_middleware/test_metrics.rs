@@ -0,0 +1,80 @@
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::{done, oneshot, Future};
+use tokio_proto::{self, Service};
+use tokio_proto::middleware::metrics::{Histogram, Metrics};
+
+#[test]
+fn test_counts_outcomes() {
+    let service = Metrics::new(tokio_proto::simple_service(|req: u32| {
+        if req % 2 == 0 {
+            done(Ok(req))
+        } else {
+            done(Err(io::Error::new(io::ErrorKind::Other, "odd")))
+        }
+    }));
+
+    for i in 0..5 {
+        let _ = service.call(i).wait();
+    }
+
+    let stats = service.sink();
+    assert_eq!(3, stats.successes());
+    assert_eq!(2, stats.errors());
+    assert_eq!(0, stats.in_flight());
+    assert_eq!(5, stats.latency().count());
+}
+
+#[test]
+fn test_tracks_in_flight_calls() {
+    let (c, fut) = oneshot();
+    let fut = Mutex::new(Some(fut));
+
+    let service = Metrics::new(tokio_proto::simple_service(move |_: u32| {
+        fut.lock().unwrap().take().unwrap().then(|r| r.unwrap())
+    }));
+
+    let resp = service.call(1);
+    assert_eq!(1, service.sink().in_flight());
+
+    c.complete(Ok::<u32, io::Error>(1));
+    assert_eq!(1, resp.wait().unwrap());
+    assert_eq!(0, service.sink().in_flight());
+}
+
+#[test]
+fn test_dropped_call_is_canceled() {
+    let (_c, fut) = oneshot::<Result<u32, io::Error>>();
+    let fut = Mutex::new(Some(fut));
+
+    let service = Metrics::new(tokio_proto::simple_service(move |_: u32| {
+        fut.lock().unwrap().take().unwrap().then(|r| r.unwrap())
+    }));
+
+    drop(service.call(1));
+
+    assert_eq!(1, service.sink().canceled());
+    assert_eq!(0, service.sink().in_flight());
+}
+
+#[test]
+fn test_histogram_percentiles() {
+    let histogram = Histogram::new();
+    assert_eq!(None, histogram.percentile(0.5));
+
+    for _ in 0..90 {
+        histogram.record(Duration::from_millis(1));
+    }
+
+    for _ in 0..10 {
+        histogram.record(Duration::from_millis(100));
+    }
+
+    let p50 = histogram.percentile(0.5).unwrap();
+    assert!(p50 >= Duration::from_millis(1) && p50 < Duration::from_millis(2));
+
+    let p99 = histogram.percentile(0.99).unwrap();
+    assert!(p99 >= Duration::from_millis(100) && p99 < Duration::from_millis(200));
+}
@@ -0,0 +1,68 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::Future;
+use tokio_proto::{self, Service};
+use tokio_proto::middleware::mirror::Mirror;
+
+#[test]
+fn test_mirrors_sampled_requests() {
+    let shadow_calls = Arc::new(AtomicUsize::new(0));
+    let shadow_calls2 = shadow_calls.clone();
+
+    let primary = tokio_proto::simple_service(|req: u32| {
+        ::futures::finished::<u32, io::Error>(req)
+    });
+
+    let shadow = tokio_proto::simple_service(move |req: u32| {
+        shadow_calls2.fetch_add(1, Ordering::SeqCst);
+        ::futures::finished::<u32, io::Error>(req)
+    });
+
+    let service = Mirror::new(primary, shadow, 0.25);
+
+    for i in 0..8 {
+        assert_eq!(i, service.call(i).wait().unwrap());
+    }
+
+    assert_eq!(2, service.mirrored());
+    assert_eq!(2, shadow_calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_shadow_errors_are_ignored() {
+    let primary = tokio_proto::simple_service(|req: u32| {
+        ::futures::finished::<u32, io::Error>(req)
+    });
+
+    let shadow = tokio_proto::simple_service(|_: u32| {
+        ::futures::failed::<u32, io::Error>(io::Error::new(io::ErrorKind::Other, "nope"))
+    });
+
+    let service = Mirror::new(primary, shadow, 1.0);
+
+    assert_eq!(7, service.call(7).wait().unwrap());
+    assert_eq!(1, service.mirrored());
+}
+
+#[test]
+fn test_comparator_counts_mismatches() {
+    let primary = tokio_proto::simple_service(|req: u32| {
+        ::futures::finished::<u32, io::Error>(req)
+    });
+
+    let shadow = tokio_proto::simple_service(|req: u32| {
+        ::futures::finished::<u32, io::Error>(if req % 2 == 0 { req } else { req + 1 })
+    });
+
+    let service = Mirror::new(primary, shadow, 1.0)
+        .comparator(|primary: &u32, shadow: &u32| primary == shadow);
+
+    for i in 0..4 {
+        assert_eq!(i, service.call(i).wait().unwrap());
+    }
+
+    assert_eq!(4, service.mirrored());
+    assert_eq!(2, service.mismatches());
+}
@@ -0,0 +1,41 @@
+use std::io;
+
+use futures::Future;
+use tokio_proto::{self, Service};
+use tokio_proto::middleware::router::Builder;
+
+fn op(req: &(&'static str, u32)) -> &'static str {
+    req.0
+}
+
+#[test]
+fn test_routes_by_key() {
+    let router = Builder::new(op)
+        .route("double", tokio_proto::simple_service(|(_, n): (&'static str, u32)| {
+            ::futures::finished::<u32, io::Error>(n * 2)
+        }))
+        .route("incr", tokio_proto::simple_service(|(_, n): (&'static str, u32)| {
+            ::futures::finished::<u32, io::Error>(n + 1)
+        }))
+        .build(tokio_proto::simple_service(|_: (&'static str, u32)| {
+            ::futures::failed::<u32, io::Error>(io::Error::new(io::ErrorKind::Other, "unknown op"))
+        }));
+
+    assert_eq!(2, router.len());
+    assert_eq!(10, router.call(("double", 5)).wait().unwrap());
+    assert_eq!(6, router.call(("incr", 5)).wait().unwrap());
+}
+
+#[test]
+fn test_unmatched_requests_use_fallback() {
+    let router = Builder::new(op)
+        .route("double", tokio_proto::simple_service(|(_, n): (&'static str, u32)| {
+            ::futures::finished::<u32, io::Error>(n * 2)
+        }))
+        .build(tokio_proto::simple_service(|_: (&'static str, u32)| {
+            ::futures::failed::<u32, io::Error>(io::Error::new(io::ErrorKind::Other, "unknown op"))
+        }));
+
+    let err = router.call(("halve", 4)).wait().unwrap_err();
+    assert_eq!("unknown op", err.to_string());
+}
@@ -0,0 +1,42 @@
+use std::io;
+use std::thread;
+
+use futures::{done, Future};
+use tokio_proto::{self, Service};
+use tokio_proto::middleware::thread_pool::{Builder, ThreadPoolError};
+
+#[test]
+fn test_runs_call_on_worker_thread() {
+    let service = tokio_proto::simple_service(|_: ()| {
+        let name = thread::current().name().map(|s| s.to_string());
+        done::<_, io::Error>(Ok(name))
+    });
+
+    let pool = Builder::new()
+        .threads(2)
+        .name_prefix("test-worker-")
+        .build(service)
+        .unwrap();
+
+    let name = pool.call(()).wait().unwrap().unwrap();
+    assert!(name.starts_with("test-worker-"), "name={}", name);
+}
+
+#[test]
+fn test_panic_is_returned_as_error() {
+    let service = tokio_proto::simple_service(|req: u32| {
+        if req == 0 {
+            panic!("boom");
+        }
+
+        done::<u32, io::Error>(Ok(req))
+    });
+
+    let pool = Builder::new().threads(1).build(service).unwrap();
+
+    let err = pool.call(0).wait().unwrap_err();
+    assert_eq!(ThreadPoolError::Panicked.to_string(), err.to_string());
+
+    // The worker thread survived the panic
+    assert_eq!(1, pool.call(1).wait().unwrap());
+}
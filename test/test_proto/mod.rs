@@ -1,2 +1,5 @@
+mod test_pipeline_balance;
+mod test_pipeline_blocking;
 mod test_pipeline_client;
+mod test_pipeline_pool;
 mod test_pipeline_server;
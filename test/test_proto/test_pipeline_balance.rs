@@ -0,0 +1,211 @@
+use std::io;
+use std::thread;
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+
+use futures::stream::{self, Receiver};
+use futures::{Future, oneshot};
+use support::{self, mock};
+use tokio_proto::Service;
+use tokio_proto::discover::Change;
+use tokio_proto::proto::pipeline;
+use tokio_core::{Loop, LoopHandle};
+
+// Transport handle
+type TransportHandle = mock::TransportHandle<Frame, Frame>;
+
+// Transport factory created for each endpoint
+type NewTransport = Box<Fn() -> io::Result<mock::Transport<Frame, Frame>> + Send>;
+
+// Endpoint discovery stream
+type Discover = Receiver<Change<u32>, io::Error>;
+
+// Discovery handle
+type Changes = stream::Sender<Change<u32>, io::Error>;
+
+// Balancer handle
+type Balance = pipeline::Balance<u32, Box<Fn(&u32) -> NewTransport + Send>, NewTransport, Body, io::Error>;
+
+// In frame
+type Frame = pipeline::Frame<&'static str, io::Error, u32>;
+
+// Body stream
+type Body = Receiver<u32, io::Error>;
+
+#[test]
+fn test_fails_without_endpoints() {
+    run(0, |_, _, balance| {
+        let err = balance.call(pipeline::Message::WithoutBody("ping")).wait().unwrap_err();
+        assert_eq!(io::ErrorKind::NotConnected, err.kind());
+    });
+}
+
+#[test]
+fn test_connects_to_inserted_endpoints() {
+    run(2, |mocks, changes, balance| {
+        let changes = changes.send(Ok(Change::Insert(0))).wait().ok().unwrap();
+        support::sleep_ms(20);
+
+        assert_eq!(1, balance.len());
+
+        mocks[0].allow_write();
+        let one = balance.call(pipeline::Message::WithoutBody("one"));
+        assert_eq!("one", mocks[0].next_write().unwrap_msg());
+
+        changes.send(Ok(Change::Insert(1))).wait().ok().unwrap();
+        support::sleep_ms(20);
+
+        assert_eq!(2, balance.len());
+
+        // The first endpoint is busy, the request goes to the second one
+        mocks[1].allow_write();
+        let two = balance.call(pipeline::Message::WithoutBody("two"));
+        assert_eq!("two", mocks[1].next_write().unwrap_msg());
+
+        mocks[0].send(pipeline::Frame::Message("resp-one"));
+        mocks[1].send(pipeline::Frame::Message("resp-two"));
+
+        assert_eq!("resp-one", one.wait().unwrap());
+        assert_eq!("resp-two", two.wait().unwrap());
+    });
+}
+
+#[test]
+fn test_disconnects_removed_endpoints() {
+    run(2, |mocks, changes, balance| {
+        let changes = changes.send(Ok(Change::Insert(0))).wait().ok().unwrap();
+        let changes = changes.send(Ok(Change::Insert(1))).wait().ok().unwrap();
+        support::sleep_ms(20);
+
+        changes.send(Ok(Change::Remove(0))).wait().ok().unwrap();
+
+        mocks[0].allow_and_assert_drop();
+        assert_eq!(1, balance.len());
+
+        mocks[1].allow_write();
+        let pong = balance.call(pipeline::Message::WithoutBody("ping"));
+        assert_eq!("ping", mocks[1].next_write().unwrap_msg());
+
+        mocks[1].send(pipeline::Frame::Message("pong"));
+        assert_eq!("pong", pong.wait().unwrap());
+    });
+}
+
+#[test]
+fn test_skips_unhealthy_endpoints() {
+    // Only the round of probes sent when checking starts is expected
+    let check = pipeline::HealthCheck::new(support::millis(60_000), || "health", |resp: &&'static str| *resp == "ok")
+        .unhealthy_threshold(1)
+        .healthy_threshold(1);
+
+    run(2, |mocks, changes, balance| {
+        let changes = changes.send(Ok(Change::Insert(0))).wait().ok().unwrap();
+        changes.send(Ok(Change::Insert(1))).wait().ok().unwrap();
+
+        support::wait_for(|| balance.len() == 2);
+
+        mocks[0].allow_write();
+        mocks[1].allow_write();
+
+        let balance = balance.health_check(check);
+
+        assert_eq!("health", mocks[0].next_write().unwrap_msg());
+        assert_eq!("health", mocks[1].next_write().unwrap_msg());
+
+        mocks[0].send(pipeline::Frame::Message("bad"));
+        mocks[1].send(pipeline::Frame::Message("ok"));
+
+        support::wait_for(|| balance.healthy() == 1);
+
+        assert_eq!(2, balance.len());
+
+        // Both requests go to the healthy endpoint, even though it is busy
+        mocks[1].allow_write();
+        mocks[1].allow_write();
+
+        let one = balance.call(pipeline::Message::WithoutBody("one"));
+        let two = balance.call(pipeline::Message::WithoutBody("two"));
+
+        assert_eq!("one", mocks[1].next_write().unwrap_msg());
+        assert_eq!("two", mocks[1].next_write().unwrap_msg());
+
+        mocks[1].send(pipeline::Frame::Message("resp-one"));
+        mocks[1].send(pipeline::Frame::Message("resp-two"));
+
+        assert_eq!("resp-one", one.wait().unwrap());
+        assert_eq!("resp-two", two.wait().unwrap());
+    });
+}
+
+#[test]
+fn test_backs_off_from_refusing_endpoints() {
+    run(0, |_, changes, balance| {
+        changes.send(Ok(Change::Insert(0))).wait().ok().unwrap();
+        support::sleep_ms(20);
+
+        // The endpoint is reconnected to once right away
+        for _ in 0..2 {
+            let err = balance.call(pipeline::Message::WithoutBody("ping")).wait().unwrap_err();
+            assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+            support::sleep_ms(20);
+        }
+
+        // Then it is skipped until the back-off elapses
+        let err = balance.call(pipeline::Message::WithoutBody("ping")).wait().unwrap_err();
+        assert_eq!(io::ErrorKind::NotConnected, err.kind());
+
+        support::sleep_ms(150);
+
+        let err = balance.call(pipeline::Message::WithoutBody("ping")).wait().unwrap_err();
+        assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+    });
+}
+
+/// Setup a reactor running a pipeline::Balance with `n` mock endpoints,
+/// keyed by their index. Yields the mock transport handles and the sending
+/// half of the discovery stream to the function.
+fn run<F>(n: u32, f: F)
+    where F: FnOnce(Vec<TransportHandle>, Changes, Balance)
+{
+    let _ = ::env_logger::init();
+
+    let (tx, rx) = oneshot();
+    let (tx2, rx2) = mpsc::channel();
+    let t = thread::spawn(move || {
+        let mut lp = Loop::new().unwrap();
+        tx2.send(lp.handle()).unwrap();
+        lp.run(rx)
+    });
+
+    let handle: LoopHandle = rx2.recv().unwrap();
+
+    let mut mocks = vec![];
+    let mut transports = HashMap::new();
+
+    for i in 0..n {
+        let (mock, new_transport) = mock::transport(handle.clone());
+        mocks.push(mock);
+        transports.insert(i, new_transport.new_transport().wait().unwrap());
+    }
+
+    let transports = Arc::new(Mutex::new(transports));
+    let new_transport: Box<Fn(&u32) -> NewTransport + Send> = Box::new(move |key: &u32| {
+        let transports = transports.clone();
+        let key = *key;
+
+        Box::new(move || {
+            // Endpoints without a mock transport refuse connections
+            transports.lock().unwrap().remove(&key).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::ConnectionRefused, "no more mock transports")
+            })
+        }) as NewTransport
+    });
+
+    let (changes, discover): (Changes, Discover) = stream::channel();
+    let balance = pipeline::Balance::new(handle, discover, new_transport);
+
+    f(mocks, changes, balance);
+
+    tx.complete(());
+    t.join().unwrap().unwrap();
+}
@@ -0,0 +1,79 @@
+use std::io;
+use std::thread;
+use std::sync::{mpsc, Mutex};
+
+use futures::Future;
+use futures::stream::Receiver;
+use support::{self, mock};
+use tokio_proto::proto::pipeline;
+
+// Transport handle
+type TransportHandle = mock::TransportHandle<Frame, Frame>;
+
+// Transport factory handed to the client
+type NewTransport = Box<Fn() -> io::Result<mock::Transport<Frame, Frame>> + Send>;
+
+// Client handle
+type Client = pipeline::BlockingClient<NewTransport, Body, io::Error>;
+
+// In frame
+type Frame = pipeline::Frame<&'static str, io::Error, u32>;
+
+// Body stream
+type Body = Receiver<u32, io::Error>;
+
+#[test]
+fn test_blocking_call() {
+    let (mock, client) = connect();
+
+    mock.allow_write();
+
+    // Respond from another thread while the call blocks
+    let t = thread::spawn(move || {
+        assert_eq!("ping", mock.next_write().unwrap_msg());
+        mock.send(pipeline::Frame::Message("pong"));
+    });
+
+    assert_eq!("pong", client.call(pipeline::Message::WithoutBody("ping")).unwrap());
+    t.join().unwrap();
+}
+
+#[test]
+fn test_call_timeout() {
+    let (mock, mut client) = connect();
+    client.set_timeout(Some(support::millis(20)));
+
+    mock.allow_write();
+
+    let err = client.call(pipeline::Message::WithoutBody("ping")).unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+}
+
+#[test]
+fn test_drop_shuts_down_loop() {
+    let (mock, client) = connect();
+
+    drop(client);
+    mock.allow_and_assert_drop();
+}
+
+/// Connect a blocking client to a mock transport created on the client's
+/// event loop.
+fn connect() -> (TransportHandle, Client) {
+    let _ = ::env_logger::init();
+
+    let (tx, rx) = mpsc::channel();
+
+    let client = pipeline::BlockingClient::connect(move |handle| {
+        let (mock, new_transport) = mock::transport(handle.clone());
+        tx.send(mock).unwrap();
+
+        let transport = Mutex::new(Some(new_transport.new_transport().wait().unwrap()));
+
+        Box::new(move || {
+            Ok(transport.lock().unwrap().take().unwrap())
+        }) as NewTransport
+    }).unwrap();
+
+    (rx.recv().unwrap(), client)
+}
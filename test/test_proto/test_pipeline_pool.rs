@@ -0,0 +1,196 @@
+use std::io;
+use std::thread;
+use std::sync::{mpsc, Mutex};
+
+use futures::stream::Receiver;
+use futures::{Future, oneshot};
+use support::{self, mock};
+use tokio_proto::Service;
+use tokio_proto::proto::pipeline::{self, pool};
+use tokio_core::{Loop, LoopHandle};
+
+// Transport handle
+type TransportHandle = mock::TransportHandle<Frame, Frame>;
+
+// Transport factory handed to the pool
+type NewTransport = Box<Fn() -> io::Result<mock::Transport<Frame, Frame>> + Send>;
+
+// Pool handle
+type Pool = pipeline::Pool<NewTransport, Body, io::Error>;
+
+// In frame
+type Frame = pipeline::Frame<&'static str, io::Error, u32>;
+
+// Body stream
+type Body = Receiver<u32, io::Error>;
+
+#[test]
+fn test_reconnects_after_connection_closed() {
+    run(2, pool::Builder::new(), |mocks, pool| {
+        mocks[0].allow_write();
+
+        let pong = pool.call(pipeline::Message::WithoutBody("ping"));
+        assert_eq!("ping", mocks[0].next_write().unwrap_msg());
+
+        mocks[0].send(pipeline::Frame::Message("pong"));
+        assert_eq!("pong", pong.wait().unwrap());
+
+        // Close the first connection
+        mocks[0].send(pipeline::Frame::Done);
+        mocks[0].allow_and_assert_drop();
+
+        support::sleep_ms(20);
+
+        mocks[1].allow_write();
+
+        let pong = pool.call(pipeline::Message::WithoutBody("ping"));
+        assert_eq!("ping", mocks[1].next_write().unwrap_msg());
+
+        mocks[1].send(pipeline::Frame::Message("pong"));
+        assert_eq!("pong", pong.wait().unwrap());
+
+        assert_eq!(1, pool.len());
+    });
+}
+
+#[test]
+fn test_opens_connection_when_all_busy() {
+    run(2, pool::Builder::new().max_connections(2), |mocks, pool| {
+        mocks[0].allow_write();
+        mocks[1].allow_write();
+
+        let one = pool.call(pipeline::Message::WithoutBody("one"));
+        assert_eq!("one", mocks[0].next_write().unwrap_msg());
+
+        // The first connection is busy, a second one is opened
+        let two = pool.call(pipeline::Message::WithoutBody("two"));
+        assert_eq!("two", mocks[1].next_write().unwrap_msg());
+
+        assert_eq!(2, pool.len());
+
+        mocks[1].send(pipeline::Frame::Message("resp-two"));
+        assert_eq!("resp-two", two.wait().unwrap());
+
+        mocks[0].send(pipeline::Frame::Message("resp-one"));
+        assert_eq!("resp-one", one.wait().unwrap());
+    });
+}
+
+#[test]
+fn test_reuses_connection_when_full() {
+    run(1, pool::Builder::new().max_connections(1), |mocks, pool| {
+        mocks[0].allow_write();
+        mocks[0].allow_write();
+
+        let one = pool.call(pipeline::Message::WithoutBody("one"));
+        let two = pool.call(pipeline::Message::WithoutBody("two"));
+
+        assert_eq!("one", mocks[0].next_write().unwrap_msg());
+        assert_eq!("two", mocks[0].next_write().unwrap_msg());
+
+        mocks[0].send(pipeline::Frame::Message("resp-one"));
+        mocks[0].send(pipeline::Frame::Message("resp-two"));
+
+        assert_eq!("resp-one", one.wait().unwrap());
+        assert_eq!("resp-two", two.wait().unwrap());
+    });
+}
+
+#[test]
+fn test_evicts_idle_connections() {
+    run(1, pool::Builder::new().idle_timeout(support::millis(50)), |mocks, pool| {
+        mocks[0].allow_write();
+
+        let pong = pool.call(pipeline::Message::WithoutBody("ping"));
+        assert_eq!("ping", mocks[0].next_write().unwrap_msg());
+
+        mocks[0].send(pipeline::Frame::Message("pong"));
+        assert_eq!("pong", pong.wait().unwrap());
+
+        assert_eq!(1, pool.len());
+
+        // The idle connection is closed without any further call
+        mocks[0].allow_and_assert_drop();
+        assert!(pool.is_empty());
+    });
+}
+
+#[test]
+fn test_replaces_unhealthy_connections() {
+    // Only the round of probes sent when checking starts is expected
+    let check = pipeline::HealthCheck::new(support::millis(60_000), || "health", |resp: &&'static str| *resp == "ok")
+        .unhealthy_threshold(1);
+
+    run(2, pool::Builder::new(), |mocks, pool| {
+        mocks[0].allow_write();
+
+        let pong = pool.call(pipeline::Message::WithoutBody("ping"));
+        assert_eq!("ping", mocks[0].next_write().unwrap_msg());
+
+        mocks[0].send(pipeline::Frame::Message("pong"));
+        assert_eq!("pong", pong.wait().unwrap());
+
+        mocks[0].allow_write();
+
+        let pool = pool.health_check(check);
+
+        assert_eq!("health", mocks[0].next_write().unwrap_msg());
+        mocks[0].send(pipeline::Frame::Message("bad"));
+
+        support::wait_for(|| pool.healthy() == 0);
+
+        // The unhealthy connection is closed and the request is dispatched on
+        // a new one
+        mocks[1].allow_write();
+
+        let pong = pool.call(pipeline::Message::WithoutBody("ping"));
+        assert_eq!("ping", mocks[1].next_write().unwrap_msg());
+
+        mocks[0].allow_and_assert_drop();
+
+        mocks[1].send(pipeline::Frame::Message("pong"));
+        assert_eq!("pong", pong.wait().unwrap());
+
+        assert_eq!(1, pool.len());
+    });
+}
+
+/// Setup a reactor running a pipeline::Pool backed by `n` mock transports,
+/// handed out in order as the pool connects. Yields the mock transport
+/// handles to the function.
+fn run<F>(n: usize, builder: pool::Builder, f: F)
+    where F: FnOnce(Vec<TransportHandle>, Pool)
+{
+    let _ = ::env_logger::init();
+
+    let (tx, rx) = oneshot();
+    let (tx2, rx2) = mpsc::channel();
+    let t = thread::spawn(move || {
+        let mut lp = Loop::new().unwrap();
+        tx2.send(lp.handle()).unwrap();
+        lp.run(rx)
+    });
+
+    let handle: LoopHandle = rx2.recv().unwrap();
+
+    let mut mocks = vec![];
+    let mut transports = vec![];
+
+    for _ in 0..n {
+        let (mock, new_transport) = mock::transport(handle.clone());
+        mocks.push(mock);
+        transports.push(new_transport.new_transport().wait().unwrap());
+    }
+
+    let transports = Mutex::new(transports.into_iter());
+    let new_transport: NewTransport = Box::new(move || {
+        Ok(transports.lock().unwrap().next().expect("no more mock transports"))
+    });
+
+    let pool = builder.build(handle, new_transport);
+
+    f(mocks, pool);
+
+    tx.complete(());
+    t.join().unwrap().unwrap();
+}
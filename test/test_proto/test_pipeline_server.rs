@@ -6,7 +6,9 @@ use futures::stream::{self, Stream, Receiver};
 use futures::{Future, failed, finished, oneshot};
 use support::{self, mock};
 use tokio_proto::proto::pipeline::{self, Frame, Message};
-use tokio_proto;
+use tokio_proto::{self, NewService, ServiceExt};
+use tokio_proto::io::{Readiness, Transport};
+use tokio_proto::middleware::router;
 use tokio_core::Loop;
 
 // The message type is a static string for both the request and response
@@ -402,6 +404,127 @@ fn test_streaming_response_body() {
     });
 }
 
+#[test]
+fn test_streaming_response_body_with_small_write_capacity() {
+    let (tx, rx) = stream::channel::<u32, io::Error>();
+    let rx = Mutex::new(Some(rx));
+
+    let service = tokio_proto::simple_service(move |req: Message<Msg, Body>| {
+        match *req {
+            "one" => finished(Message::WithBody("resp-one", rx.lock().unwrap().take().unwrap())),
+            _ => finished(Message::WithoutBody("resp-two")),
+        }
+    });
+
+    let body = thread::spawn(move || {
+        let tx = tx.send(Ok(1)).wait().ok().unwrap();
+        tx.send(Ok(2)).wait().ok().unwrap();
+    });
+
+    // The transport accepts a single frame between flushes
+    run_capped(service, 1, |mock| {
+        mock.send(msg("one"));
+        mock.send(msg("two"));
+
+        mock.allow_write();
+        assert_eq!("resp-one", mock.next_write().unwrap_msg());
+
+        mock.allow_write();
+        assert_eq!(Some(1), mock.next_write().unwrap_body());
+
+        mock.allow_write();
+        assert_eq!(Some(2), mock.next_write().unwrap_body());
+
+        mock.allow_write();
+        assert_eq!(None, mock.next_write().unwrap_body());
+
+        mock.allow_write();
+        assert_eq!("resp-two", mock.next_write().unwrap_msg());
+
+        mock.send(Frame::Done);
+        mock.allow_and_assert_drop();
+    });
+
+    body.join().unwrap();
+}
+
+#[test]
+fn test_adapted_service() {
+    let service = tokio_proto::simple_service(|req: Message<Msg, Body>| {
+        finished::<Msg, io::Error>(*req)
+    });
+
+    let service = service
+        .map_request(|mut req: Message<Msg, Body>| {
+            if *req == "hello" {
+                *req = "goodbye";
+            }
+
+            req
+        })
+        .map_response(|resp| Message::WithoutBody(resp));
+
+    run(service, |mock| {
+        mock.allow_write();
+        mock.send(msg("hello"));
+        assert_eq!(mock.next_write().unwrap_msg(), "goodbye");
+
+        mock.send(Frame::Done);
+        mock.allow_and_assert_drop();
+    });
+}
+
+#[test]
+fn test_serve_waits_for_service() {
+    let (c, fut) = oneshot();
+    let fut = Mutex::new(Some(fut));
+
+    let new_service = move || {
+        fut.lock().unwrap().take().unwrap().then(|r| r.unwrap())
+    };
+
+    run_serve(new_service, |mock| {
+        mock.allow_write();
+        mock.send(msg("hello"));
+
+        // The service is not yet available
+        mock.assert_no_write(20);
+
+        c.complete(Ok(tokio_proto::simple_service(|req| finished(req))));
+        assert_eq!(mock.next_write().unwrap_msg(), "hello");
+
+        mock.send(Frame::Done);
+        mock.allow_and_assert_drop();
+    });
+}
+
+#[test]
+fn test_serving_router() {
+    let router = router::Builder::new(|req: &Message<Msg, Body>| **req)
+        .route("hello", tokio_proto::simple_service(|_: Message<Msg, Body>| {
+            finished::<_, io::Error>(Message::WithoutBody("world"))
+        }))
+        .build(tokio_proto::simple_service(|req: Message<Msg, Body>| {
+            finished::<_, io::Error>(Message::WithoutBody(*req))
+        }));
+
+    run(router.new_service().unwrap(), |mock| {
+        mock.allow_write();
+        mock.send(msg("hello"));
+        assert_eq!(mock.next_write().unwrap_msg(), "world");
+
+        mock.allow_write();
+        mock.send(msg("ping"));
+        assert_eq!(mock.next_write().unwrap_msg(), "ping");
+
+        mock.send(Frame::Done);
+        mock.allow_and_assert_drop();
+    });
+
+    // The router is still usable after handing out a service
+    assert_eq!(1, router.len());
+}
+
 fn channel<T>() -> (Arc<Mutex<mpsc::Sender<T>>>, mpsc::Receiver<T>) {
     let (tx, rx) = mpsc::channel();
     let tx = Arc::new(Mutex::new(tx));
@@ -444,3 +567,104 @@ fn run<S, F>(service: S, f: F)
     tx.complete(());
     t.join().unwrap().unwrap();
 }
+
+/// Setup a reactor running a pipeline::Server with the given service and a
+/// mock transport accepting at most `cap` frames between flushes. Yields the
+/// mock transport handle to the function.
+fn run_capped<S, F>(service: S, cap: usize, f: F)
+    where S: pipeline::ServerService<Req = pipeline::Message<Msg, Body>, Resp = Msg, Body = u32, BodyStream = Body, Error = io::Error>,
+          F: FnOnce(mock::TransportHandle<InFrame, OutFrame>),
+{
+    drop(::env_logger::init());
+    let (tx, rx) = oneshot();
+    let (tx2, rx2) = mpsc::channel();
+    let t = thread::spawn(move || {
+        let mut lp = Loop::new().unwrap();
+        tx2.send(lp.handle()).unwrap();
+        lp.run(rx)
+    });
+    let handle = rx2.recv().unwrap();
+
+    let (mock, new_transport) = mock::transport::<InFrame, OutFrame>(handle);
+
+    let transport = Capped {
+        inner: new_transport.new_transport().wait().unwrap(),
+        cap: cap,
+        written: 0,
+    };
+
+    let dispatch = pipeline::Server::new(service, transport).unwrap();
+    dispatch.forget();
+
+    f(mock);
+
+    tx.complete(());
+    t.join().unwrap().unwrap();
+}
+
+/// Setup a reactor running `pipeline::serve` with the given service factory
+/// and a mock transport. Yields the mock transport handle to the function.
+fn run_serve<N, F>(new_service: N, f: F)
+    where N: tokio_proto::AsyncNewService + Send + 'static,
+          N::Item: pipeline::ServerService<Req = pipeline::Message<Msg, Body>, Resp = Msg, Body = u32, BodyStream = Body, Error = io::Error>,
+          N::Fut: Send + 'static,
+          F: FnOnce(mock::TransportHandle<InFrame, OutFrame>),
+{
+    drop(::env_logger::init());
+    let (tx, rx) = oneshot();
+    let (tx2, rx2) = mpsc::channel();
+    let t = thread::spawn(move || {
+        let mut lp = Loop::new().unwrap();
+        tx2.send(lp.handle()).unwrap();
+        lp.run(rx)
+    });
+    let handle = rx2.recv().unwrap();
+
+    let (mock, new_transport) = mock::transport::<InFrame, OutFrame>(handle);
+
+    let transport = new_transport.new_transport().wait().unwrap();
+    pipeline::serve(&new_service, transport).forget();
+
+    f(mock);
+
+    tx.complete(());
+    t.join().unwrap().unwrap();
+}
+
+// Transport accepting at most `cap` frames between flushes, like a buffered
+// transport reaching its high water mark
+struct Capped<T> {
+    inner: T,
+    cap: usize,
+    written: usize,
+}
+
+impl<T: Readiness> Readiness for Capped<T> {
+    fn is_readable(&self) -> bool {
+        self.inner.is_readable()
+    }
+
+    fn is_writable(&self) -> bool {
+        self.written < self.cap && self.inner.is_writable()
+    }
+}
+
+impl<T: Transport> Transport for Capped<T> {
+    type In = T::In;
+    type Out = T::Out;
+
+    fn read(&mut self) -> io::Result<Option<T::Out>> {
+        self.inner.read()
+    }
+
+    fn write(&mut self, req: T::In) -> io::Result<Option<()>> {
+        assert!(self.written < self.cap, "cannot write past capacity");
+        self.written += 1;
+        self.inner.write(req)
+    }
+
+    fn flush(&mut self) -> io::Result<Option<()>> {
+        self.written = 0;
+        self.inner.flush()
+    }
+}
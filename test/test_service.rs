@@ -0,0 +1,103 @@
+use std::io;
+
+use futures::{finished, failed, Finished, Future};
+use tokio_proto::{self, Service, ServiceExt};
+
+#[derive(Clone)]
+struct Echo;
+
+impl Service for Echo {
+    type Req = u32;
+    type Resp = u32;
+    type Error = io::Error;
+    type Fut = Finished<u32, io::Error>;
+
+    fn call(&self, req: u32) -> Self::Fut {
+        finished(req)
+    }
+}
+
+#[test]
+fn test_map_request() {
+    let service = Echo.map_request(|req: &'static str| req.len() as u32);
+    assert_eq!(5, service.call("hello").wait().unwrap());
+}
+
+#[test]
+fn test_map_response() {
+    let service = Echo.map_response(|resp| resp * 2);
+    assert_eq!(6, service.call(3).wait().unwrap());
+}
+
+#[test]
+fn test_map_err() {
+    let service = tokio_proto::simple_service(|_: u32| {
+        failed::<u32, io::Error>(io::Error::new(io::ErrorKind::Other, "nope"))
+    });
+
+    let service = service.map_err(|e| e.kind());
+    assert_eq!(io::ErrorKind::Other, service.call(1).wait().unwrap_err());
+}
+
+#[test]
+fn test_and_then() {
+    let service = Echo.and_then(|resp| {
+        if resp > 10 {
+            Err(io::Error::new(io::ErrorKind::Other, "too big"))
+        } else {
+            Ok(resp + 1)
+        }
+    });
+
+    assert_eq!(2, service.call(1).wait().unwrap());
+    assert!(service.call(11).wait().is_err());
+}
+
+#[test]
+fn test_and_then_skipped_on_error() {
+    let service = tokio_proto::simple_service(|_: u32| {
+        failed::<u32, io::Error>(io::Error::new(io::ErrorKind::Other, "nope"))
+    });
+
+    let service = service.and_then(|_| -> Result<u32, io::Error> {
+        panic!("and_then called on error");
+    });
+
+    assert!(service.call(1).wait().is_err());
+}
+
+#[test]
+fn test_then_recovers_from_error() {
+    let service = tokio_proto::simple_service(|_: u32| {
+        failed::<u32, io::Error>(io::Error::new(io::ErrorKind::Other, "nope"))
+    });
+
+    let service = service.then(|res| {
+        finished::<_, ()>(res.unwrap_or(0))
+    });
+
+    assert_eq!(0, service.call(1).wait().unwrap());
+}
+
+#[test]
+fn test_combinators_compose() {
+    let service = Echo
+        .map_request(|req: &'static str| req.len() as u32)
+        .map_response(|resp| resp.to_string())
+        .map_err(|_| ());
+
+    // Adapters of a `Clone` service are `Clone`
+    let service = service.clone();
+    assert_eq!("3", service.call("abc").wait().unwrap());
+}
+
+#[test]
+fn test_boxed() {
+    let services = vec![
+        Echo.boxed(),
+        Echo.map_response(|resp| resp + 1).boxed(),
+    ];
+
+    assert_eq!(1, services[0].call(1).wait().unwrap());
+    assert_eq!(2, services[1].call(1).wait().unwrap());
+}